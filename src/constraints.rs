@@ -0,0 +1,215 @@
+//! Constraint layout, enabled with the `constraints` feature. Lays nodes out
+//! relative to each other with linear constraints solved by cassowary, on top
+//! of a normal layout, e.g. to line up nodes in different subtrees.
+//!
+//! Each node with variables is suggested the rect it was given by
+//! Node::layout() at weak strength, so nodes keep their laid out rect unless a
+//! stronger constraint moves them. Nodes that are moved are laid out again in
+//! their new rect, along with their descendants.
+
+use std::collections::HashMap;
+
+use cassowary::{AddConstraintError, Solver};
+pub use cassowary::{strength, Constraint, Expression, Variable, WeightedRelation};
+
+use {Clipping, LayoutCtx, LayoutError, Node, Rect, Tree};
+
+/// The variables for the rect of a node, to use in constraints.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeVars {
+  pub left: Variable,
+  pub top: Variable,
+  pub width: Variable,
+  pub height: Variable,
+}
+
+impl NodeVars {
+  pub fn right(&self) -> Expression {
+    self.left + self.width
+  }
+
+  pub fn bottom(&self) -> Expression {
+    self.top + self.height
+  }
+}
+
+/// A set of constraints between the rects of nodes, and the solver for them.
+/// Constraints persist between layouts, so this can be kept around and
+/// applied after each layout.
+pub struct ConstraintLayout {
+  solver: Solver,
+  vars: HashMap<u32, NodeVars>,
+}
+
+impl Default for ConstraintLayout {
+  fn default() -> ConstraintLayout {
+    ConstraintLayout::new()
+  }
+}
+
+impl ConstraintLayout {
+  pub fn new() -> ConstraintLayout {
+    ConstraintLayout { solver: Solver::new(), vars: HashMap::new() }
+  }
+
+  /// Gets the variables for the rect of the node with the given id, creating
+  /// them the first time. Widths and heights are never negative.
+  pub fn vars(&mut self, id: u32) -> NodeVars {
+    let solver = &mut self.solver;
+    *self.vars.entry(id).or_insert_with(|| {
+      let vars = NodeVars {
+        left: Variable::new(), top: Variable::new(), width: Variable::new(), height: Variable::new(),
+      };
+      for &v in &[vars.left, vars.top, vars.width, vars.height] {
+        solver.add_edit_variable(v, strength::WEAK).expect("new edit variable");
+      }
+      solver.add_constraints(&[
+        vars.width | WeightedRelation::GE(strength::REQUIRED) | 0.0,
+        vars.height | WeightedRelation::GE(strength::REQUIRED) | 0.0,
+      ]).expect("new size constraint");
+      vars
+    })
+  }
+
+  /// Adds a constraint between node variables, e.g.
+  /// `a.right() | EQ(REQUIRED) | b.left`.
+  /// # Errors
+  /// If the constraint was already added, or it's required and conflicts with
+  /// the required constraints already added.
+  pub fn add_constraint(&mut self, constraint: Constraint) -> Result<(), AddConstraintError> {
+    self.solver.add_constraint(constraint)
+  }
+
+  pub fn add_constraints(&mut self, constraints: &[Constraint]) -> Result<(), AddConstraintError> {
+    self.solver.add_constraints(constraints)
+  }
+
+  /// Solves the constraints and writes the results into a rect buffer that
+  /// `root` has just been laid out into with Node::layout(). Each node with
+  /// variables is laid out again into its solved rect, so its descendants
+  /// fit it.
+  /// # Errors
+  /// LayoutError::BufferTooSmall if the buffer is too small for `root`, or
+  /// any error from laying out a node into its solved rect.
+  pub fn apply(&mut self, root: &Node, rect_buffer: &mut [Rect]) -> Result<(), LayoutError> {
+    self.apply_with_measure(root, rect_buffer, &mut |_, _| [0.0, 0.0])
+  }
+
+  /// Like apply(), for trees laid out with Node::layout_with_measure(), to
+  /// measure auto sized nodes with the same function when they're laid out
+  /// again.
+  pub fn apply_with_measure(&mut self, root: &Node, rect_buffer: &mut [Rect], measure: &mut dyn FnMut(u32, [f32; 2]) -> [f32; 2]) -> Result<(), LayoutError> {
+    let required = root.rect_count(Tree::Owned);
+    if rect_buffer.len() < required {
+      return Err(LayoutError::BufferTooSmall { id: root.id, len: rect_buffer.len(), required });
+    }
+    let rect_buffer = &mut rect_buffer[..required];
+    for r in rect_buffer.iter() {
+      if let Some(vars) = self.vars.get(&r.id) {
+        for &(v, value) in &[(vars.left, r.pos[0]), (vars.top, r.pos[1]), (vars.width, r.size[0]), (vars.height, r.size[1])] {
+          self.solver.suggest_value(v, value as f64).expect("edit variable");
+        }
+      }
+    }
+    self.place(root, rect_buffer, Clipping::default(), &mut LayoutCtx::new(Tree::Owned, measure))
+  }
+
+  /// Lays out the nodes with variables in a node tree again into their
+  /// solved rects, top down, so nodes inside a moved node can still be placed
+  /// exactly. `clipping` is how the node's ancestors clip it.
+  fn place(&self, node: &Node, rect_buffer: &mut [Rect], clipping: Clipping, ctx: &mut LayoutCtx) -> Result<(), LayoutError> {
+    let last = rect_buffer.len() - 1;
+    if let Some(vars) = self.vars.get(&node.id) {
+      let pos = [self.solver.get_value(vars.left) as f32, self.solver.get_value(vars.top) as f32];
+      let size = [self.solver.get_value(vars.width) as f32, self.solver.get_value(vars.height) as f32];
+      let layer = rect_buffer[last].layer;
+      node.layout_node(rect_buffer, pos, size, layer, clipping, ctx)?;
+    }
+    let c_clipping = node.child_clipping(clipping, rect_buffer[last].pos, rect_buffer[last].size);
+    let mut curr_index = 0;
+    for c in &node.children {
+      let count = c.rect_count(Tree::Owned);
+      self.place(c, &mut rect_buffer[curr_index..curr_index + count], c_clipping, ctx)?;
+      curr_index += count;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::strength::{MEDIUM, REQUIRED, STRONG};
+  use super::WeightedRelation::EQ;
+  use {DynLen, Layout, Overflow};
+
+  #[test]
+  fn align_across_subtrees() {
+    // Two rows of a label and a field, with the fields lined up.
+    let mut form = Node::new(0, Layout::Vertical, DynLen::Relative(1.0));
+    let mut name_row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    let mut name_field = Node::new(3, Layout::Horizontal, DynLen::Relative(1.0));
+    name_field.add_child(Node::new(7, Layout::Vertical, DynLen::Relative(1.0)));
+    name_row.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(50.0)), name_field]);
+    let mut address_row = Node::new(4, Layout::Horizontal, DynLen::Relative(1.0));
+    address_row.add_children(vec![Node::new(5, Layout::Vertical, DynLen::Absolute(80.0)),
+                                  Node::new(6, Layout::Vertical, DynLen::Relative(1.0))]);
+    form.add_children(vec![name_row, address_row]);
+    let mut rects = form.alloc_rect_buffer();
+    form.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+
+    let mut constraints = ConstraintLayout::new();
+    let label = constraints.vars(5);
+    let field = constraints.vars(3);
+    constraints.add_constraints(&[
+      label.left | EQ(STRONG) | 0.0,
+      label.width | EQ(STRONG) | 80.0,
+      field.left | EQ(REQUIRED) | label.right(),
+      field.right() | EQ(MEDIUM) | 400.0,
+    ]).unwrap();
+    constraints.apply(&form, &mut rects).unwrap();
+
+    assert_eq!(rects[2].id, 3);
+    assert_eq!(rects[2].pos, [80.0, 0.0]);
+    assert_eq!(rects[2].size, [320.0, 50.0]);
+    // The field's child is laid out again to fill it.
+    assert_eq!(rects[1].pos, [80.0, 0.0]);
+    assert_eq!(rects[1].size, [320.0, 50.0]);
+    // Nodes without constraints are left alone.
+    assert_eq!(rects[4].id, 5);
+    assert_eq!(rects[4].size, [80.0, 50.0]);
+
+    // Laying out again undoes the constraints, until they're applied again.
+    form.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[1].size, [350.0, 50.0]);
+    constraints.apply(&form, &mut rects).unwrap();
+    assert_eq!(rects[1].size, [320.0, 50.0]);
+
+    assert_eq!(constraints.apply(&form, &mut rects[1..]),
+               Err(LayoutError::BufferTooSmall { id: 0, len: 7, required: 8 }));
+  }
+
+  #[test]
+  fn move_clipping_node() {
+    let mut root = Node::new(0, Layout::Horizontal, DynLen::Relative(1.0));
+    let mut panel = Node::new(1, Layout::Horizontal, DynLen::Absolute(100.0));
+    panel.set_clip_children(true);
+    panel.set_overflow(Overflow::Clip);
+    panel.add_child(Node::new(2, Layout::Vertical, DynLen::Absolute(150.0)));
+    root.add_children(vec![panel, Node::new(3, Layout::Vertical, DynLen::Relative(1.0))]);
+    let mut rects = root.alloc_rect_buffer();
+    root.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[0].clip, Some(([0.0, 0.0], [100.0, 100.0])));
+
+    let mut constraints = ConstraintLayout::new();
+    let panel = constraints.vars(1);
+    constraints.add_constraint(panel.left | EQ(REQUIRED) | 250.0).unwrap();
+    constraints.apply(&root, &mut rects).unwrap();
+
+    // The child is cut off at, and clipped to, the panel where it's moved to.
+    assert_eq!(rects[1].pos, [250.0, 0.0]);
+    assert_eq!(rects[0].pos, [250.0, 0.0]);
+    assert_eq!(rects[0].size, [100.0, 100.0]);
+    assert_eq!(rects[0].clip, Some(([250.0, 0.0], [100.0, 100.0])));
+  }
+}
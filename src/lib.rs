@@ -1,5 +1,24 @@
+#[cfg(feature = "constraints")]
+extern crate cassowary;
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ops::Range;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+mod result;
+mod tree;
+pub use result::LayoutResult;
+pub use tree::{LayoutTree, NodeHandle};
+
+#[cfg(feature = "constraints")]
+pub mod constraints;
+
 /// Struct for a dynamic length
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DynLen {
   /// A relative length, with a number as the proportion (as a ratio with other
   /// relatively sized components) of free size this length takes up.
@@ -9,149 +28,2746 @@ pub enum DynLen {
 
   /// An absolute length, doesn't change according to parent size.
   Absolute(f32),
+
+  /// A percentage of the parent's full length along the same axis (inside its
+  /// padding), regardless of any siblings. Sized like an absolute length once
+  /// resolved, before relative lengths split the free space.
+  Percent(f32),
+
+  /// The node's own intrinsic size. Nodes with children fit their content,
+  /// and leaves are given the size returned by the measure function passed to
+  /// Node::layout_with_measure(). Sized like an absolute length once
+  /// measured, but won't shrink below the content's minimum size.
+  Auto,
+
+  /// A flexible length, like a flexbox item. Starts at `basis` pixels, then
+  /// takes a share of any free space in proportion to `grow`, or gives up
+  /// space when there isn't enough in proportion to `shrink` times `basis`.
+  /// Lengths outside a line of siblings (e.g. across the layout axis) fill
+  /// the space like a relative length.
+  Flex { basis: f32, grow: f32, shrink: f32 },
 }
 
-#[derive(Debug, Clone)]
+impl DynLen {
+  /// The length in pixels, given the full length of the parent to take
+  /// percentages of. None for relative and flexible lengths, which depend on
+  /// their siblings, and auto lengths, which need measuring.
+  fn fixed(&self, full: f32) -> Option<f32> {
+    match *self {
+      DynLen::Absolute(l) => Some(l),
+      DynLen::Percent(p) => Some(full * p / 100.0),
+      DynLen::Relative(_) | DynLen::Auto | DynLen::Flex { .. } => None,
+    }
+  }
+
+  /// Replaces an auto length with the given measured length.
+  fn or_measured(self, measured: f32) -> DynLen {
+    match self {
+      DynLen::Auto => DynLen::Absolute(measured),
+      len => len,
+    }
+  }
+}
+
+/// Error returned by Node::layout when a node tree can't be laid out. Each
+/// variant carries the id (or handle) of the offending node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutError {
+  /// The absolutely sized children of node `id`, along with the min sizes of
+  /// the others, need `required` space along the layout axis, but only
+  /// `available` was given.
+  InsufficientSpace { id: u32, required: f32, available: f32 },
+
+  /// The rect buffer holds `len` rects, but the tree under node `id` needs
+  /// `required`.
+  BufferTooSmall { id: u32, len: usize, required: usize },
+
+  /// Node `id` has relatively sized children, but their proportions sum to
+  /// `ratio_sum`, so there's no way to split free space between them.
+  ZeroRelativeRatio { id: u32, ratio_sum: f32 },
+
+  /// Node `id` is placed at `row`, `column` (including its span) in a grid
+  /// which only has `rows` rows and `columns` columns.
+  GridCellOutOfRange { id: u32, row: usize, column: usize, rows: usize, columns: usize },
+
+  /// Node `id` has an aspect ratio that isn't a positive, finite number.
+  InvalidAspectRatio { id: u32, ratio: f32 },
+
+  /// Node `id` was given a position, size or layer (or has a length) that is
+  /// NaN or infinite.
+  NonFinite { id: u32, value: f32 },
+
+  /// Node `id` was given a size (or has a length, padding or gap) that is
+  /// negative.
+  Negative { id: u32, value: f32 },
+
+  /// More than one node has the id `id`, so their rects can't be told apart.
+  DuplicateId { id: u32 },
+
+  /// `handle` was given as the root of a LayoutTree, but its node has been
+  /// removed from the tree.
+  NotInTree { handle: NodeHandle },
+}
+
+impl fmt::Display for LayoutError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      LayoutError::InsufficientSpace { id, required, available } =>
+        write!(f, "node {} needs {} space for absolute children, but only has {}", id, required, available),
+      LayoutError::BufferTooSmall { id, len, required } =>
+        write!(f, "rect buffer of length {} is too small for node {}, which needs {}", len, id, required),
+      LayoutError::ZeroRelativeRatio { id, ratio_sum } =>
+        write!(f, "relative children of node {} have a total ratio of {}", id, ratio_sum),
+      LayoutError::GridCellOutOfRange { id, row, column, rows, columns } =>
+        write!(f, "node {} is placed at row {}, column {}, outside of a {}x{} grid", id, row, column, rows, columns),
+      LayoutError::InvalidAspectRatio { id, ratio } =>
+        write!(f, "node {} has an invalid aspect ratio {}", id, ratio),
+      LayoutError::NonFinite { id, value } =>
+        write!(f, "node {} has a non-finite value {}", id, value),
+      LayoutError::Negative { id, value } =>
+        write!(f, "node {} has a negative value {}", id, value),
+      LayoutError::DuplicateId { id } =>
+        write!(f, "more than one node has the id {}", id),
+      LayoutError::NotInTree { handle } =>
+        write!(f, "{:?} isn't in the layout tree", handle),
+    }
+  }
+}
+
+impl Error for LayoutError {}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Layout {
-  Horizontal, Vertical
+  Horizontal, Vertical,
+
+  /// Like Horizontal, but children that don't fit in a row wrap onto a new
+  /// row below.
+  HorizontalWrap,
+
+  /// Like Vertical, but children that don't fit in a column wrap onto a new
+  /// column to the right.
+  VerticalWrap,
+
+  /// Children are placed into the cells of a grid, given by the lengths of
+  /// each column and row. Absolute tracks are sized first, and relative
+  /// tracks share the space left, the same way as children in a horizontal
+  /// or vertical layout. The node's gap spaces out columns and its line gap
+  /// spaces out rows. See NodeProps::set_grid_cell().
+  Grid { columns: Vec<DynLen>, rows: Vec<DynLen> },
+
+  /// Children are stacked on top of each other, each in a higher layer than
+  /// everything in the children before it. A child's size is its width and
+  /// its cross size its height; relative (or no) lengths fill the node. The
+  /// node's justify mode places children horizontally and the cross
+  /// alignment vertically.
+  Stack,
+}
+
+impl Layout {
+  /// Index of the axis children are laid out along, for indexing `[x, y]`
+  /// pairs.
+  fn main_axis(&self) -> usize {
+    match *self {
+      Layout::Horizontal | Layout::HorizontalWrap | Layout::Grid { .. } | Layout::Stack => 0,
+      Layout::Vertical | Layout::VerticalWrap => 1,
+    }
+  }
+
+  fn wraps(&self) -> bool {
+    match *self {
+      Layout::HorizontalWrap | Layout::VerticalWrap => true,
+      Layout::Horizontal | Layout::Vertical | Layout::Grid { .. } | Layout::Stack => false,
+    }
+  }
+}
+
+/// Where to place a child across the layout axis, within the space its parent
+/// has for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+  /// Against the left (in a vertical layout) or top (in a horizontal layout).
+  Start,
+  Center,
+  /// Against the right (in a vertical layout) or bottom (in a horizontal
+  /// layout).
+  End,
+  /// Fill the space, unless the child has an absolute cross size in which
+  /// case this is the same as Start. This is the default.
+  Stretch,
+}
+
+impl Align {
+  /// The offset from the start of the space to place something given the
+  /// amount of free space around it.
+  fn offset(&self, free_space: f32) -> f32 {
+    match *self {
+      Align::Start | Align::Stretch => 0.0,
+      Align::Center => free_space / 2.0,
+      Align::End => free_space,
+    }
+  }
+}
+
+/// How to place children along the layout axis when they don't fill it. Only
+/// used when there are no relatively sized children to take up the space, or
+/// they are all held back by their max size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Justify {
+  /// Pack children against the left or top. This is the default.
+  Start,
+  /// Pack children against the right or bottom.
+  End,
+  Center,
+  /// Put the first and last child against the edges, and share the space
+  /// between the others.
+  SpaceBetween,
+  /// Give each child the same space either side, so the space at the edges is
+  /// half that between children.
+  SpaceAround,
+  /// Make the space at the edges and between each child all the same.
+  SpaceEvenly,
+}
+
+impl Justify {
+  /// Splits `free_space` around `n` items, returning the extra space before
+  /// the first item and between each item. There's nothing to place if
+  /// there are no items.
+  fn offsets(&self, free_space: f32, n: usize) -> (f32, f32) {
+    if n == 0 {
+      return (0.0, 0.0);
+    }
+    let n = n as f32;
+    match *self {
+      Justify::Start => (0.0, 0.0),
+      Justify::End => (free_space, 0.0),
+      Justify::Center => (free_space / 2.0, 0.0),
+      Justify::SpaceBetween if n > 1.0 => (0.0, free_space / (n - 1.0)),
+      Justify::SpaceBetween => (0.0, 0.0),
+      Justify::SpaceAround => (free_space / (2.0 * n), free_space / n),
+      Justify::SpaceEvenly => (free_space / (n + 1.0), free_space / (n + 1.0)),
+    }
+  }
+}
+
+/// The cells a child of a grid layout covers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridCell {
+  pub row: usize,
+  pub column: usize,
+  /// Number of rows covered, going down from `row`.
+  pub row_span: usize,
+  /// Number of columns covered, going right from `column`.
+  pub column_span: usize,
+}
+
+impl GridCell {
+  /// A single cell.
+  pub fn new(row: usize, column: usize) -> GridCell {
+    GridCell { row, column, row_span: 1, column_span: 1 }
+  }
+
+  /// A block of cells with its top left at the given row and column.
+  pub fn spanning(row: usize, column: usize, row_span: usize, column_span: usize) -> GridCell {
+    GridCell { row, column, row_span, column_span }
+  }
+}
+
+/// How a node is positioned within its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+  /// Laid out along with its siblings by the parent's layout. This is the
+  /// default.
+  Flow,
+
+  /// Placed by offsets from the edges of the parent's rect, ignoring the
+  /// parent's layout and padding. Takes no space from its siblings, and is
+  /// drawn above all of them.
+  Anchored(Anchors),
+}
+
+/// Offsets from each edge of a parent's rect for an anchored node. A relative
+/// offset is that fraction of the parent's width or height.
+///
+/// With both offsets on an axis, the node fills the space between them
+/// unless it has an absolute length along that axis, in which case the left
+/// or top offset wins. With one offset, the node is placed against that edge.
+/// With neither, it's placed against the left or top.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Anchors {
+  pub left: Option<DynLen>,
+  pub top: Option<DynLen>,
+  pub right: Option<DynLen>,
+  pub bottom: Option<DynLen>,
+}
+
+/// How a node with an aspect ratio fits in the rect its parent gives it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+  /// As large as possible while staying inside the rect.
+  Contain,
+  /// As small as possible while still covering the rect, spilling out of it
+  /// on one axis.
+  Cover,
+}
+
+/// A fixed ratio of width to height for a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AspectRatio {
+  /// Width divided by height, e.g. 16.0 / 9.0.
+  pub ratio: f32,
+  pub fit: Fit,
+  /// Horizontal alignment in the space left (or overflowing) around the
+  /// node. Stretch is the same as Start.
+  pub align_x: Align,
+  /// Vertical alignment in the space left (or overflowing) around the node.
+  /// Stretch is the same as Start.
+  pub align_y: Align,
+}
+
+impl AspectRatio {
+  /// An aspect ratio centered in the space around it.
+  pub fn new(ratio: f32, fit: Fit) -> AspectRatio {
+    AspectRatio { ratio, fit, align_x: Align::Center, align_y: Align::Center }
+  }
+
+  /// Fits a rect with this aspect ratio to the given rect.
+  fn fit(&self, pos: [f32; 2], size: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+    let w = match self.fit {
+      Fit::Contain => size[0].min(size[1] * self.ratio),
+      Fit::Cover => size[0].max(size[1] * self.ratio),
+    };
+    let fitted = [w, w / self.ratio];
+    ([pos[0] + self.align_x.offset(size[0] - fitted[0]),
+      pos[1] + self.align_y.offset(size[1] - fitted[1])], fitted)
+  }
+
+  /// The length along `axis` that keeps this ratio with `other`, the length
+  /// along the other axis.
+  fn len(&self, axis: usize, other: f32) -> f32 {
+    if axis == 0 { other * self.ratio } else { other / self.ratio }
+  }
+}
+
+/// Lengths for each side of a rectangle, used for padding and margins.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Sides {
+  pub left: f32,
+  pub top: f32,
+  pub right: f32,
+  pub bottom: f32,
+}
+
+impl Sides {
+  pub fn new(left: f32, top: f32, right: f32, bottom: f32) -> Sides {
+    Sides { left, top, right, bottom }
+  }
+
+  /// The same length on every side.
+  pub fn all(l: f32) -> Sides {
+    Sides::new(l, l, l, l)
+  }
+
+  /// The left or top length, for the x or y axis respectively.
+  fn start(&self, axis: usize) -> f32 {
+    if axis == 0 { self.left } else { self.top }
+  }
+
+  /// The right or bottom length, for the x or y axis respectively.
+  fn end(&self, axis: usize) -> f32 {
+    if axis == 0 { self.right } else { self.bottom }
+  }
+
+  /// The total length along the given axis.
+  fn sum(&self, axis: usize) -> f32 {
+    self.start(axis) + self.end(axis)
+  }
+}
+
+/// What a node does when its absolutely sized children need more space along
+/// the layout axis than the node has.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+  /// Fail with LayoutError::InsufficientSpace. This is the default. In
+  /// wrapping layouts, a child too big for a line overflows a line of its
+  /// own instead.
+  Deny,
+
+  /// Keep the absolute sizes and let the children spill out past the edge of
+  /// the node. Relatively sized children get no space.
+  Visible,
+
+  /// Scale the absolutely sized children down proportionally so they fit,
+  /// though never below their min size. Relatively sized children get no
+  /// space.
+  Shrink,
+
+  /// Like Visible, but the rects of all descendants are cut off at the edge of
+  /// the node, and clipped to it like with NodeProps::set_clip_children().
+  Clip,
+
+  /// Lay the children out into a content extent as large as they need (but
+  /// no smaller than the node), moved back by the node's scroll offset. The
+  /// rects of all descendants are given the node's rect as their clip rect,
+  /// for renderers to scissor them with, and the node's rect records the
+  /// content extent in `content_size`. Wrapping layouts extend across the
+  /// layout axis, adding lines, rather than along it.
+  Scroll,
+}
+
+/// The first column and row of a grid cell, and the number of columns and
+/// rows it spans.
+type CellSpan = ([usize; 2], [usize; 2]);
+
+/// The minimum and preferred width and height of a node, worked out from its
+/// children by the bottom-up pass of layout.
+#[derive(Debug, Clone, Copy, Default)]
+struct Intrinsic {
+  min: [f32; 2],
+  pref: [f32; 2],
+}
+
+/// Where the children of the nodes being laid out are kept.
+#[derive(Clone, Copy)]
+enum Tree<'a> {
+  /// In each node's `children`.
+  Owned,
+  /// In a LayoutTree, by each node's `child_handles`.
+  Arena(&'a LayoutTree),
+}
+
+impl<'a> Tree<'a> {
+  fn children(self, node: &'a Node) -> Children<'a> {
+    match self {
+      Tree::Owned => Children::Owned(node.children.iter()),
+      Tree::Arena(tree) => Children::Arena(node.child_handles.iter(), tree),
+    }
+  }
+}
+
+/// Iterator over the children of a node, wherever they're kept.
+#[derive(Clone)]
+enum Children<'a> {
+  Owned(slice::Iter<'a, Node>),
+  Arena(slice::Iter<'a, NodeHandle>, &'a LayoutTree),
+}
+
+impl<'a> Iterator for Children<'a> {
+  type Item = &'a Node;
+
+  fn next(&mut self) -> Option<&'a Node> {
+    match *self {
+      Children::Owned(ref mut iter) => iter.next(),
+      Children::Arena(ref mut iter, tree) => iter.next().map(|&h| tree.node(h).expect("child in tree")),
+    }
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    match *self {
+      Children::Owned(ref iter) => iter.size_hint(),
+      Children::Arena(ref iter, _) => iter.size_hint(),
+    }
+  }
+}
+
+impl<'a> ExactSizeIterator for Children<'a> {}
+
+/// What laying out a node needs besides the node: where to find children,
+/// and how to measure auto sized leaves.
+struct LayoutCtx<'a, 'm> {
+  tree: Tree<'a>,
+  measure: &'m mut dyn FnMut(u32, [f32; 2]) -> [f32; 2],
+  /// Number of this layout, counting up over every layout of any tree.
+  layout: usize,
+}
+
+/// Number of layouts started, to order them for caching.
+static LAYOUTS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(test)]
+thread_local!(static RECT_WRITES: Cell<usize> = const { Cell::new(0) });
+
+/// Writes a node's rect into the buffer, counting writes in tests.
+fn write_rect(slot: &mut Rect, rect: Rect) {
+  #[cfg(test)]
+  RECT_WRITES.with(|w| w.set(w.get() + 1));
+  *slot = rect;
+}
+
+impl<'a, 'm> LayoutCtx<'a, 'm> {
+  fn new(tree: Tree<'a>, measure: &'m mut dyn FnMut(u32, [f32; 2]) -> [f32; 2]) -> LayoutCtx<'a, 'm> {
+    LayoutCtx { tree, measure, layout: LAYOUTS.fetch_add(1, Ordering::Relaxed) + 1 }
+  }
 }
 
+/// How a node's ancestors clip it: the bounds its rect is cut off at by
+/// ancestors with Overflow::Clip, and its clip rect.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Clipping {
+  cut: Option<([f32; 2], [f32; 2])>,
+  clip: Option<([f32; 2], [f32; 2])>,
+}
+
+/// Everything a node's layout depends on, besides the node tree itself, to
+/// tell when a clean node can be skipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LayoutInput {
+  pos: [f32; 2],
+  size: [f32; 2],
+  layer: f32,
+  clipping: Clipping,
+}
+
+/// What a node's last layout produced, to skip it next time if nothing has
+/// changed.
+#[derive(Debug, Clone, Copy)]
+struct Cached {
+  input: LayoutInput,
+  /// The node's own rect, and the number of rects in its tree.
+  rect: Rect,
+  count: usize,
+  /// The number of the layout that produced these.
+  layout: usize,
+}
+
+/// The properties of a node, besides its children: how it's sized by its
+/// parent, and how it lays out its children. Nodes deref to their properties,
+/// and nodes in a LayoutTree are reached as just their properties, as their
+/// children are kept in the tree.
 #[derive(Debug, Clone)]
-pub struct Node {
+pub struct NodeProps {
   id: u32,
   children_layout : Layout,
-  children: Vec<Node>,
   size: DynLen,
+  cross_size: Option<DynLen>,
+  align_self: Option<Align>,
+  cross_align: Align,
+  justify: Justify,
+  min_size: Option<f32>,
+  max_size: Option<f32>,
+  grid_cell: Option<GridCell>,
+  position: Position,
+  aspect_ratio: Option<AspectRatio>,
+  padding: Sides,
+  margin: Sides,
+  gap: f32,
+  leading_gap: f32,
+  trailing_gap: f32,
+  line_gap: f32,
+  align_lines: Justify,
+  overflow: Overflow,
+  reverse: bool,
+  scroll_offset: [f32; 2],
+  clip_children: bool,
+  intrinsic: Cell<Intrinsic>,
+  dirty: Cell<bool>,
+  cache: Cell<Option<Cached>>,
+}
+
+/// A node in a layout tree, owning its children.
+///
+/// Nodes keep what they worked out in their last layout, so later layouts can
+/// skip them, in cells. So a `Node` is `Send`, but unlike in earlier versions
+/// not `Sync`: to lay out a tree shared between threads, put it in a `Mutex`
+/// rather than sharing an `Arc<Node>`.
+#[derive(Debug, Clone)]
+pub struct Node {
+  props: NodeProps,
+  children: Vec<Node>,
+  /// Children of nodes in a LayoutTree, kept in the tree rather than here.
+  child_handles: Vec<NodeHandle>,
+}
+
+impl Deref for Node {
+  type Target = NodeProps;
+
+  fn deref(&self) -> &NodeProps {
+    &self.props
+  }
+}
+
+impl DerefMut for Node {
+  fn deref_mut(&mut self) -> &mut NodeProps {
+    &mut self.props
+  }
 }
 
 impl Node {
   pub fn new(id: u32, children_layout: Layout, size: DynLen) -> Node {
     Node {
-      id: id, 
-      children_layout: children_layout, 
-      children: Vec::new(), 
-      size: size,
+      props: NodeProps {
+        id,
+        children_layout,
+        size,
+        cross_size: None,
+        align_self: None,
+        cross_align: Align::Stretch,
+        justify: Justify::Start,
+        min_size: None,
+        max_size: None,
+        grid_cell: None,
+        position: Position::Flow,
+        aspect_ratio: None,
+        padding: Sides::default(),
+        margin: Sides::default(),
+        gap: 0.0,
+        leading_gap: 0.0,
+        trailing_gap: 0.0,
+        line_gap: 0.0,
+        align_lines: Justify::Start,
+        overflow: Overflow::Deny,
+        reverse: false,
+        scroll_offset: [0.0; 2],
+        clip_children: false,
+        intrinsic: Cell::new(Intrinsic::default()),
+        dirty: Cell::new(true),
+        cache: Cell::new(None),
+      },
+      children: Vec::new(),
+      child_handles: Vec::new(),
+    }
+  }
+}
+
+impl NodeProps {
+  pub fn id(&self) -> u32 { self.id }
+  pub fn children_layout(&self) -> &Layout { &self.children_layout }
+  pub fn size(&self) -> DynLen { self.size }
+  pub fn cross_size(&self) -> Option<DynLen> { self.cross_size }
+  pub fn align_self(&self) -> Option<Align> { self.align_self }
+  pub fn cross_align(&self) -> Align { self.cross_align }
+  pub fn justify(&self) -> Justify { self.justify }
+  pub fn min_size(&self) -> Option<f32> { self.min_size }
+  pub fn max_size(&self) -> Option<f32> { self.max_size }
+  pub fn grid_cell(&self) -> Option<GridCell> { self.grid_cell }
+  pub fn position(&self) -> Position { self.position }
+  pub fn aspect_ratio(&self) -> Option<AspectRatio> { self.aspect_ratio }
+  pub fn padding(&self) -> Sides { self.padding }
+  pub fn margin(&self) -> Sides { self.margin }
+  pub fn gap(&self) -> f32 { self.gap }
+  /// The leading and trailing gaps, see set_outer_gaps().
+  pub fn outer_gaps(&self) -> (f32, f32) { (self.leading_gap, self.trailing_gap) }
+  pub fn line_gap(&self) -> f32 { self.line_gap }
+  pub fn align_lines(&self) -> Justify { self.align_lines }
+  pub fn overflow(&self) -> Overflow { self.overflow }
+  pub fn reverse(&self) -> bool { self.reverse }
+  pub fn scroll_offset(&self) -> [f32; 2] { self.scroll_offset }
+  pub fn clip_children(&self) -> bool { self.clip_children }
+
+  /// Sets the id given to this node's rect. Ids should be unique in a tree.
+  pub fn set_id(&mut self, id: u32) {
+    self.dirty.set(true);
+    self.id = id;
+  }
+
+  /// Sets how this node's children are laid out.
+  pub fn set_children_layout(&mut self, children_layout: Layout) {
+    self.dirty.set(true);
+    self.children_layout = children_layout;
+  }
+
+  /// Sets the size of this node along its parent's layout axis, e.g.
+  /// DynLen::Absolute(0.0) to collapse a panel.
+  pub fn set_size(&mut self, size: DynLen) {
+    self.dirty.set(true);
+    self.size = size;
+  }
+
+  /// Sets the cells this node covers when its parent has a grid layout. Nodes
+  /// without a cell, the default, take the next cell in order, a row at a
+  /// time, regardless of the cells other children cover.
+  pub fn set_grid_cell(&mut self, cell: Option<GridCell>) {
+    self.dirty.set(true);
+    self.grid_cell = cell;
+  }
+
+  /// Sets whether this node is laid out by its parent's layout or anchored
+  /// to the parent's edges. Anchored nodes take their size along the
+  /// parent's layout axis from their size and min / max size, and the other
+  /// from their cross size, like in the flow.
+  pub fn set_position(&mut self, position: Position) {
+    self.dirty.set(true);
+    self.position = position;
+  }
+
+  /// Sets a fixed ratio of width to height for this node, or None for no
+  /// ratio. If one of its size and cross size is DynLen::Auto, it's worked
+  /// out from the other with the ratio, in place of measuring it. Otherwise
+  /// the node is fitted, and aligned, inside whatever rect its size and cross
+  /// size would give it.
+  pub fn set_aspect_ratio(&mut self, aspect_ratio: Option<AspectRatio>) {
+    self.dirty.set(true);
+    self.aspect_ratio = aspect_ratio;
+  }
+
+  fn is_anchored(&self) -> bool {
+    matches!(self.position, Position::Anchored(_))
+  }
+
+  /// Sets the space between the edges of this node and its children.
+  pub fn set_padding(&mut self, padding: Sides) {
+    self.dirty.set(true);
+    self.padding = padding;
+  }
+
+  /// Sets the space kept clear around this node, taken from its parent's
+  /// free space. Margins are outside the node's rect and aren't included in
+  /// its size or min / max size.
+  pub fn set_margin(&mut self, margin: Sides) {
+    self.dirty.set(true);
+    self.margin = margin;
+  }
+
+  /// Sets the size of this node across its parent's layout axis (its height
+  /// in a horizontal layout, or width in a vertical one). None, the default,
+  /// fills the space available. A relative cross size has no siblings to
+  /// share with, so also fills the space. A percentage cross size is taken of
+  /// the parent's size inside its padding, or of its line in a wrapping
+  /// layout.
+  pub fn set_cross_size(&mut self, cross_size: Option<DynLen>) {
+    self.dirty.set(true);
+    self.cross_size = cross_size;
+  }
+
+  /// Sets how this node's children are aligned across the layout axis.
+  /// Defaults to Align::Stretch.
+  pub fn set_cross_align(&mut self, align: Align) {
+    self.dirty.set(true);
+    self.cross_align = align;
+  }
+
+  /// Sets how this node's children are placed along the layout axis when they
+  /// don't fill it. Defaults to Justify::Start.
+  pub fn set_justify(&mut self, justify: Justify) {
+    self.dirty.set(true);
+    self.justify = justify;
+  }
+
+  /// Overrides the parent's cross alignment for this node, or None to use
+  /// the parent's.
+  pub fn set_align_self(&mut self, align: Option<Align>) {
+    self.dirty.set(true);
+    self.align_self = align;
+  }
+
+  /// Sets the smallest size this node can be given along its parent's layout
+  /// axis, or None for no lower bound. Takes priority over the max size.
+  pub fn set_min_size(&mut self, min_size: Option<f32>) {
+    self.dirty.set(true);
+    self.min_size = min_size;
+  }
+
+  /// Sets the largest size this node can be given along its parent's layout
+  /// axis, or None for no upper bound.
+  pub fn set_max_size(&mut self, max_size: Option<f32>) {
+    self.dirty.set(true);
+    self.max_size = max_size;
+  }
+
+  /// Sets the space left between each pair of neighbouring children along the
+  /// layout axis.
+  pub fn set_gap(&mut self, gap: f32) {
+    self.dirty.set(true);
+    self.gap = gap;
+  }
+
+  /// Sets the space left before the first child and after the last child
+  /// along the layout axis. These are on top of any padding.
+  pub fn set_outer_gaps(&mut self, leading: f32, trailing: f32) {
+    self.dirty.set(true);
+    self.leading_gap = leading;
+    self.trailing_gap = trailing;
+  }
+
+  /// Sets the space left between lines in a wrapping layout.
+  pub fn set_line_gap(&mut self, line_gap: f32) {
+    self.dirty.set(true);
+    self.line_gap = line_gap;
+  }
+
+  /// Sets how the lines of a wrapping layout are placed across the layout
+  /// axis when they don't fill it. Each line is as big as its largest
+  /// child; children without an absolute cross size fill their line.
+  /// Defaults to Justify::Start.
+  pub fn set_align_lines(&mut self, align_lines: Justify) {
+    self.dirty.set(true);
+    self.align_lines = align_lines;
+  }
+
+  /// Sets what happens when the absolutely sized children don't fit in this
+  /// node. Defaults to Overflow::Deny.
+  pub fn set_overflow(&mut self, overflow: Overflow) {
+    self.dirty.set(true);
+    self.overflow = overflow;
+  }
+
+  /// Sets whether children are placed in reverse along the layout axis: right
+  /// to left in horizontal layouts (and grid columns), or bottom to top in
+  /// vertical ones. Gaps, margins and justify are mirrored with them. The
+  /// order of the children, and of their rects in the buffer, is unchanged.
+  pub fn set_reverse(&mut self, reverse: bool) {
+    self.dirty.set(true);
+    self.reverse = reverse;
+  }
+
+  /// Sets how far the content of a node with Overflow::Scroll is scrolled
+  /// right and down from its start. Anchored children don't scroll.
+  pub fn set_scroll_offset(&mut self, scroll_offset: [f32; 2]) {
+    self.dirty.set(true);
+    self.scroll_offset = scroll_offset;
+  }
+
+  /// Sets whether this node clips its descendants to its rect. Their rects
+  /// are left as they are, with the node's rect intersected into their clip
+  /// rect for renderers to scissor them with. Nodes with Overflow::Clip or
+  /// Overflow::Scroll always clip.
+  pub fn set_clip_children(&mut self, clip_children: bool) {
+    self.dirty.set(true);
+    self.clip_children = clip_children;
+  }
+
+  /// Marks this node as needing layout, e.g. when the size its measure
+  /// function gives has changed. Setters mark nodes automatically. Use
+  /// update() to reach a node in a tree, so its ancestors are marked too.
+  pub fn mark_dirty(&mut self) {
+    self.dirty.set(true);
+  }
+
+}
+
+impl Node {
+  /// The children of this node, in order.
+  pub fn children<'a>(&'a self) -> slice::Iter<'a, Node> {
+    self.children.iter()
+  }
+
+  /// The children of this node, to change in place. Marks this node as
+  /// needing layout, so changes to the children are picked up.
+  pub fn children_mut<'a>(&'a mut self) -> slice::IterMut<'a, Node> {
+    self.dirty.set(true);
+    self.children.iter_mut()
+  }
+
+  /// Finds the node with the given id in this tree and changes it with `f`,
+  /// marking it and its ancestors as needing layout.
+  /// # Returns
+  /// Whether a node with the id was found.
+  pub fn update<F: FnOnce(&mut Node)>(&mut self, id: u32, f: F) -> bool {
+    let path = match self.find_path(id) {
+      Some(path) => path,
+      None => return false,
+    };
+    let mut node = self;
+    node.dirty.set(true);
+    for &ii in path.iter().rev() {
+      node = &mut node.children[ii];
+      node.dirty.set(true);
+    }
+    f(node);
+    true
+  }
+
+  /// The indices of the children leading from this node to the node with
+  /// the given id, deepest first.
+  fn find_path(&self, id: u32) -> Option<Vec<usize>> {
+    if self.id == id {
+      return Some(Vec::new());
+    }
+    for (ii, c) in self.children.iter().enumerate() {
+      if let Some(mut path) = c.find_path(id) {
+        path.push(ii);
+        return Some(path);
+      }
+    }
+    None
+  }
+
+  /// The clipping of this node's children, given its own clipping and rect.
+  fn child_clipping(&self, clipping: Clipping, pos: [f32; 2], size: [f32; 2]) -> Clipping {
+    let narrow = |outer: Option<([f32; 2], [f32; 2])>| Some(match outer {
+      Some(outer) => intersect(outer, (pos, size)),
+      None => (pos, size),
+    });
+    Clipping {
+      cut: if let Overflow::Clip = self.overflow { narrow(clipping.cut) } else { clipping.cut },
+      clip: if self.clip_children || matches!(self.overflow, Overflow::Clip | Overflow::Scroll) {
+        narrow(clipping.clip)
+      } else {
+        clipping.clip
+      },
+    }
+  }
+
+  /// Mirrors the position of a child along the layout axis, between `start`
+  /// and `start + len`, if this node's layout is reversed.
+  fn directed(&self, c_pos: f32, c_len: f32, start: f32, len: f32) -> f32 {
+    if self.reverse { 2.0 * start + len - c_pos - c_len } else { c_pos }
+  }
+
+  pub fn add_child(&mut self, child: Node) {
+    self.dirty.set(true);
+    self.children.push(child);
+  }
+  pub fn add_children(&mut self, mut children: Vec<Node>) {
+    self.dirty.set(true);
+    self.children.append(&mut children);
+  }
+
+  /// Inserts a child at the given index, or after the last child if past the
+  /// end.
+  pub fn insert_child(&mut self, index: usize, child: Node) {
+    self.dirty.set(true);
+    let index = index.min(self.children.len());
+    self.children.insert(index, child);
+  }
+
+  /// Removes the child at the given index, or returns None if there isn't
+  /// one.
+  pub fn remove_child(&mut self, index: usize) -> Option<Node> {
+    if index >= self.children.len() {
+      return None;
+    }
+    self.dirty.set(true);
+    Some(self.children.remove(index))
+  }
+
+  /// Swaps the children at the given indices, or returns false and leaves
+  /// them alone if either index is out of bounds.
+  pub fn swap_children(&mut self, a: usize, b: usize) -> bool {
+    if a >= self.children.len() || b >= self.children.len() {
+      return false;
+    }
+    self.dirty.set(true);
+    self.children.swap(a, b);
+    true
+  }
+
+  /// Keeps only the children for which `f` returns true.
+  pub fn retain_children<F: FnMut(&Node) -> bool>(&mut self, f: F) {
+    self.dirty.set(true);
+    self.children.retain(f);
+  }
+
+  /// Removes all the children of this node, returning them.
+  pub fn take_children(&mut self) -> Vec<Node> {
+    self.dirty.set(true);
+    std::mem::take(&mut self.children)
+  }
+
+  /// Finds the node with the given id in this tree. Use update() to change
+  /// it.
+  pub fn find(&self, id: u32) -> Option<&Node> {
+    if self.id == id {
+      return Some(self);
+    }
+    self.children.iter().filter_map(|c| c.find(id)).next()
+  }
+
+  /// Creates a buffer of Rect structs to be used when laying out.
+  pub fn alloc_rect_buffer(&self) -> Vec<Rect> {
+    let mut buf = Vec::with_capacity(self.children.len() + 1);
+    self.alloc_rects(Tree::Owned, &mut buf);
+    buf
+  }
+
+  /// Resizes a buffer from alloc_rect_buffer() to fit this node tree after
+  /// children have been added or removed, only reallocating if it grows past
+  /// its capacity.
+  pub fn resize_rect_buffer(&self, buf: &mut Vec<Rect>) {
+    buf.resize(self.rect_count(Tree::Owned), Rect::new(self.id));
+  }
+
+  /// Adds the rects of this node tree to a new rect buffer.
+  fn alloc_rects(&self, tree: Tree, buf: &mut Vec<Rect>) {
+    for c in tree.children(self) {
+      c.alloc_rects(tree, buf);
+    }
+    buf.push(Rect::new(self.id));
+  }
+
+  /// Checks this node's lengths are all finite, and that its sizes, grid
+  /// tracks, padding and gaps aren't negative. Margins and anchor offsets can
+  /// be negative, to pull nodes past their neighbours or parent.
+  fn check_lengths(&self) -> Result<(), LayoutError> {
+    let finite = |v: f32| if v.is_finite() { Ok(()) } else { Err(LayoutError::NonFinite { id: self.id, value: v }) };
+    let positive = |v: f32| {
+      finite(v)?;
+      if v < 0.0 { Err(LayoutError::Negative { id: self.id, value: v }) } else { Ok(()) }
+    };
+    let check_len = |len: &DynLen, check: &dyn Fn(f32) -> Result<(), LayoutError>| match *len {
+      DynLen::Absolute(l) | DynLen::Relative(l) | DynLen::Percent(l) => check(l),
+      DynLen::Flex { basis, grow, shrink } => {
+        check(basis)?;
+        check(grow)?;
+        check(shrink)
+      }
+      DynLen::Auto => Ok(()),
+    };
+    let (columns, rows): (&[DynLen], &[DynLen]) = match self.children_layout {
+      Layout::Grid { ref columns, ref rows } => (columns, rows),
+      _ => (&[], &[]),
+    };
+    for len in Some(&self.size).into_iter().chain(self.cross_size.as_ref()).chain(columns).chain(rows) {
+      check_len(len, &positive)?;
+    }
+    if let Position::Anchored(a) = self.position {
+      for len in [a.left, a.top, a.right, a.bottom].iter().flatten() {
+        check_len(len, &finite)?;
+      }
+    }
+    for &v in self.min_size.iter().chain(self.max_size.iter()) {
+      positive(v)?;
+    }
+    for &v in &[self.padding.left, self.padding.top, self.padding.right, self.padding.bottom] {
+      positive(v)?;
+    }
+    for &v in &[self.margin.left, self.margin.top, self.margin.right, self.margin.bottom] {
+      finite(v)?;
+    }
+    for &v in &[self.gap, self.leading_gap, self.trailing_gap, self.line_gap] {
+      positive(v)?;
+    }
+    if let Some(AspectRatio { ratio, .. }) = self.aspect_ratio {
+      if !(ratio.is_finite() && ratio > 0.0) {
+        return Err(LayoutError::InvalidAspectRatio { id: self.id, ratio });
+      }
+    }
+    Ok(())
+  }
+
+  /// Counts the rects this node tree lays out into, i.e. the length of the
+  /// buffer returned by alloc_rect_buffer().
+  fn rect_count(&self, tree: Tree) -> usize {
+    tree.children(self).map(|c| c.rect_count(tree)).sum::<usize>() + 1
+  }
+
+  fn has_auto_size(&self) -> bool {
+    self.size == DynLen::Auto || self.cross_size == Some(DynLen::Auto)
+  }
+
+  /// Asks the measure function for this node's intrinsic width and height,
+  /// given the space available to it.
+  fn measure(&self, available: [f32; 2], ctx: &mut LayoutCtx) -> Result<[f32; 2], LayoutError> {
+    let size = (ctx.measure)(self.id, available);
+    for &v in &size {
+      if !v.is_finite() {
+        return Err(LayoutError::NonFinite { id: self.id, value: v });
+      }
+    }
+    Ok(size)
+  }
+
+  /// This node's preferred width and height. Nodes with children fit them, as
+  /// worked out by measure_intrinsic(), and leaves ask the measure function.
+  fn measure_content(&self, available: [f32; 2], ctx: &mut LayoutCtx) -> Result<[f32; 2], LayoutError> {
+    if ctx.tree.children(self).any(|c| !c.is_anchored()) {
+      Ok(self.intrinsic.get().pref)
+    } else {
+      self.measure(available, ctx)
+    }
+  }
+
+  /// Like measure_content(), but only for nodes with an auto length, returning
+  /// zero otherwise, given the parent's layout axis. An auto length is taken
+  /// from the other length by the aspect ratio, if the node has one and the
+  /// other length is known.
+  fn measure_auto(&self, available: [f32; 2], main: usize, ctx: &mut LayoutCtx) -> Result<[f32; 2], LayoutError> {
+    if !self.has_auto_size() {
+      return Ok([0.0; 2]);
+    }
+    let mut measured = self.measure_content(available, ctx)?;
+    if let Some(aspect) = self.aspect_ratio {
+      for (axis, m) in measured.iter_mut().enumerate() {
+        let other = 1 - axis;
+        let known = match self.axis_len(other, main) {
+          Some(DynLen::Auto) => None,
+          Some(l) => l.fixed(available[other]),
+          None => Some(available[other]),
+        };
+        if let (Some(DynLen::Auto), Some(known)) = (self.axis_len(axis, main), known) {
+          *m = aspect.len(axis, known);
+        }
+      }
+    }
+    Ok(measured)
+  }
+
+  /// This node's length along an axis of its parent, given the parent's
+  /// layout axis: its size along it, and its cross size across it.
+  fn axis_len(&self, axis: usize, main: usize) -> Option<DynLen> {
+    if axis == main { Some(self.size) } else { self.cross_size }
+  }
+
+  /// This node's auto length along an axis of its parent, taken by its aspect
+  /// ratio from an absolute length along the other axis.
+  fn ratio_len(&self, axis: usize, main: usize) -> Option<f32> {
+    match (self.aspect_ratio, self.axis_len(axis, main), self.axis_len(1 - axis, main)) {
+      (Some(aspect), Some(DynLen::Auto), Some(DynLen::Absolute(other))) => Some(aspect.len(axis, other)),
+      _ => None,
+    }
+  }
+
+  /// This node's min size along its parent's layout axis. Auto sized nodes
+  /// can't go below their intrinsic minimum unless given a min size.
+  fn min_len(&self, axis: usize) -> Option<f32> {
+    match self.size {
+      DynLen::Auto if self.min_size.is_none() => Some(self.intrinsic.get().min[axis]),
+      _ => self.min_size,
+    }
+  }
+
+  /// The minimum and preferred space this node takes up along an axis of its
+  /// parent, including margins, given the parent's layout axis. Absolute
+  /// lengths are fixed, and anything else takes the node's intrinsic size.
+  fn contribution(&self, axis: usize, main: usize) -> [f32; 2] {
+    let intrinsic = self.intrinsic.get();
+    let mut c = match (self.axis_len(axis, main), self.ratio_len(axis, main)) {
+      (_, Some(l)) | (Some(DynLen::Absolute(l)), _) => [l, l],
+      (Some(DynLen::Flex { basis, shrink, .. }), _) if axis == main =>
+        [if shrink > 0.0 { 0.0 } else { basis }, basis],
+      _ => [intrinsic.min[axis], intrinsic.pref[axis]],
+    };
+    if axis == main {
+      c = [clamp_len(c[0], self.min_size, self.max_size), clamp_len(c[1], self.min_size, self.max_size)];
+    }
+    [c[0] + self.margin.sum(axis), c[1] + self.margin.sum(axis)]
+  }
+
+  /// Bottom-up pass of layout. Works out the minimum and preferred size of
+  /// every node in this tree, children first, for the top-down pass to size
+  /// auto lengths with. Leaves with an auto length are measured with no space
+  /// for their minimum size, and infinite space for their preferred size.
+  fn measure_intrinsic(&self, ctx: &mut LayoutCtx) -> Result<(), LayoutError> {
+    // Clean subtrees keep their sizes from the last layout.
+    if !self.dirty.get() {
+      return Ok(());
+    }
+    let tree = ctx.tree;
+    for c in tree.children(self) {
+      c.measure_intrinsic(ctx)?;
+    }
+    let flow: Vec<&Node> = tree.children(self).filter(|c| !c.is_anchored()).collect();
+    let intrinsic = if flow.is_empty() {
+      if self.has_auto_size() {
+        Intrinsic { min: self.measure([0.0; 2], ctx)?, pref: self.measure([f32::INFINITY; 2], ctx)? }
+      } else {
+        Intrinsic::default()
+      }
+    } else {
+      let mut content = self.content_intrinsic(tree, &flow)?;
+      for axis in 0..2 {
+        content.min[axis] += self.padding.sum(axis);
+        content.pref[axis] += self.padding.sum(axis);
+      }
+      content
+    };
+    self.intrinsic.set(intrinsic);
+    Ok(())
+  }
+
+  /// The minimum and preferred size of the given flow children laid out by
+  /// this node, not counting its padding. The children's own intrinsic sizes
+  /// are known.
+  fn content_intrinsic(&self, tree: Tree, flow: &[&Node]) -> Result<Intrinsic, LayoutError> {
+    let main = self.children_layout.main_axis();
+    let cross = 1 - main;
+    let mut content = Intrinsic::default();
+    match self.children_layout {
+      Layout::Grid { ref columns, ref rows } => {
+        // Absolute tracks are fixed, the rest are as big as the largest child
+        // covering just that track.
+        let defs = [columns, rows];
+        let cells = self.grid_cells(tree)?;
+        for (axis, &gap) in [self.gap, self.line_gap].iter().enumerate() {
+          let mut tracks: Vec<[f32; 2]> = defs[axis].iter().map(|&l| match l {
+            DynLen::Absolute(l) => [l, l],
+            DynLen::Flex { basis, shrink, .. } => [if shrink > 0.0 { 0.0 } else { basis }, basis],
+            DynLen::Relative(_) | DynLen::Percent(_) | DynLen::Auto => [0.0, 0.0],
+          }).collect();
+          for (c, &(start, span)) in flow.iter().zip(cells.iter()) {
+            if span[axis] == 1 && defs[axis][start[axis]].fixed(0.0).is_none() &&
+             !matches!(defs[axis][start[axis]], DynLen::Flex { .. }) {
+              let intrinsic = c.intrinsic.get();
+              let track = &mut tracks[start[axis]];
+              track[0] = track[0].max(intrinsic.min[axis] + c.margin.sum(axis));
+              track[1] = track[1].max(intrinsic.pref[axis] + c.margin.sum(axis));
+            }
+          }
+          let spacing = gap * tracks.len().saturating_sub(1) as f32;
+          content.min[axis] = spacing + tracks.iter().map(|t| t[0]).sum::<f32>();
+          content.pref[axis] = spacing + tracks.iter().map(|t| t[1]).sum::<f32>();
+        }
+      }
+      Layout::Stack => {
+        for c in flow {
+          for axis in 0..2 {
+            let c_len = c.contribution(axis, main);
+            content.min[axis] = content.min[axis].max(c_len[0]);
+            content.pref[axis] = content.pref[axis].max(c_len[1]);
+          }
+        }
+      }
+      Layout::Horizontal | Layout::Vertical | Layout::HorizontalWrap | Layout::VerticalWrap => {
+        let outer_gaps = self.leading_gap + self.trailing_gap;
+        let spacing = outer_gaps + self.gap * (flow.len() - 1) as f32;
+        content.min[main] = spacing;
+        content.pref[main] = spacing;
+        for c in flow {
+          let (c_main, c_cross) = (c.contribution(main, main), c.contribution(cross, main));
+          content.min[main] += c_main[0];
+          content.pref[main] += c_main[1];
+          content.min[cross] = content.min[cross].max(c_cross[0]);
+          content.pref[cross] = content.pref[cross].max(c_cross[1]);
+        }
+        if self.children_layout.wraps() {
+          // At its narrowest, each child gets a line to itself.
+          content.min[main] = outer_gaps;
+          content.min[cross] = self.line_gap * (flow.len() - 1) as f32;
+          for c in flow {
+            content.min[main] = content.min[main].max(outer_gaps + c.contribution(main, main)[0]);
+            content.min[cross] += c.contribution(cross, main)[0];
+          }
+        }
+      }
+    }
+    Ok(content)
+  }
+
+  /// Layout this node tree, storing final rectangles in the given buffer of rects. 
+  /// Subtrees that haven't changed since they were last laid out, and are
+  /// given the same space, are skipped, and only those of their rects the
+  /// buffer no longer has are written back.
+  /// # Params
+  /// * `rect_buffer` - A buffer of rectangles to avoid repeated allocations on
+  ///   many layouts per frame. Use alloc_rect_buffer() to create a buffer of
+  ///   the correct size.
+  /// * `x` - The x position of the final node tree
+  /// * `y` - The y position of the final node tree
+  /// * `w` - The width given to layout the nodes.
+  /// * `h` - The height given to layout the nodes.
+  /// * `layer` - The lowest z index layer this node should reside in.
+  ///   Children's z indexes will be increased by 1 for each 'layer' in the
+  ///   tree.
+  /// # Returns
+  /// The number of rectangles written to the buffer.
+  /// # Errors
+  /// A LayoutError describing the offending node if the tree can't be laid
+  /// out. The contents of `rect_buffer` are unspecified in this case.
+  pub fn layout(&self, rect_buffer: &mut [Rect], x: f32, y: f32, w: f32, h: f32, layer: f32) -> Result<usize, LayoutError> {
+    self.layout_with_measure(rect_buffer, x, y, w, h, layer, &mut |_, _| [0.0, 0.0])
+  }
+
+  /// Like layout(), but sizes nodes with DynLen::Auto lengths using the given
+  /// measure function, e.g. to fit a label to its text. It's passed the id of
+  /// the node and the width and height available to it, and returns the
+  /// node's intrinsic width and height. It may be called more than once for
+  /// a node in each layout, and is given 0 by 0 and infinite space to find
+  /// its minimum and preferred sizes. layout() measures every node as 0 by 0.
+  /// Skipped subtrees aren't measured again, so mark nodes dirty when their
+  /// measured size changes.
+  #[allow(clippy::too_many_arguments)]
+  pub fn layout_with_measure(&self, rect_buffer: &mut [Rect], x: f32, y: f32, w: f32, h: f32, layer: f32, measure: &mut dyn FnMut(u32, [f32; 2]) -> [f32; 2]) -> Result<usize, LayoutError> {
+    self.layout_root(rect_buffer, [x, y], [w, h], layer, &mut LayoutCtx::new(Tree::Owned, measure))
+  }
+
+  /// Like layout(), into a new buffer indexed by node id.
+  /// # Errors
+  /// As layout(), or LayoutError::DuplicateId if ids in the tree aren't
+  /// unique.
+  pub fn layout_result(&self, x: f32, y: f32, w: f32, h: f32, layer: f32) -> Result<LayoutResult, LayoutError> {
+    let mut rects = self.alloc_rect_buffer();
+    self.layout(&mut rects, x, y, w, h, layer)?;
+    LayoutResult::new(rects)
+  }
+
+  /// Checks the inputs to layout, then lays out this node as the root of a
+  /// tree.
+  fn layout_root(&self, rect_buffer: &mut [Rect], pos: [f32; 2], size: [f32; 2], layer: f32, ctx: &mut LayoutCtx) -> Result<usize, LayoutError> {
+    for &v in pos.iter().chain(size.iter()).chain(Some(&layer)) {
+      if !v.is_finite() {
+        return Err(LayoutError::NonFinite { id: self.id, value: v });
+      }
+    }
+    for &v in &size {
+      if v < 0.0 {
+        return Err(LayoutError::Negative { id: self.id, value: v });
+      }
+    }
+    let required = self.rect_count(ctx.tree);
+    if rect_buffer.len() < required {
+      return Err(LayoutError::BufferTooSmall { id: self.id, len: rect_buffer.len(), required });
+    }
+    self.check_lengths()?;
+    self.measure_intrinsic(ctx)?;
+    self.layout_node(rect_buffer, pos, size, layer, Clipping::default(), ctx)
+  }
+
+  /// Recursive part of layout(). The rect buffer is known to be large enough.
+  fn layout_node(&self, rect_buffer: &mut [Rect], pos: [f32; 2], size: [f32; 2], layer: f32, clipping: Clipping, ctx: &mut LayoutCtx) -> Result<usize, LayoutError> {
+    // Nothing to lay out if this node and its inputs haven't changed since it
+    // was last laid out, just the rects from then to put back, as the buffer
+    // may have been changed since.
+    let input = LayoutInput { pos, size, layer, clipping };
+    if let Some(cached) = self.cache.get() {
+      if cached.input == input && self.restore(rect_buffer, cached.layout, ctx.tree) {
+        return Ok(cached.count);
+      }
+    }
+
+    let (pos, size) = match self.aspect_ratio {
+      Some(aspect) => aspect.fit(pos, size),
+      None => (pos, size),
+    };
+    let main = self.children_layout.main_axis();
+    let cross = 1 - main;
+    let tree = ctx.tree;
+    for c in tree.children(self) {
+      c.check_lengths()?;
+    }
+
+    // Children are laid out inside the padding.
+    let mut inner_pos = [pos[0] + self.padding.left, pos[1] + self.padding.top];
+    let mut inner_size = [(size[0] - self.padding.sum(0)).max(0.0),
+                          (size[1] - self.padding.sum(1)).max(0.0)];
+
+    // Scrolling nodes give their children all the space they'd like, scrolled
+    // by the offset.
+    if let Overflow::Scroll = self.overflow {
+      let content = self.intrinsic.get().pref;
+      for axis in 0..2 {
+        if !(self.children_layout.wraps() && axis == main) {
+          inner_size[axis] = inner_size[axis].max(content[axis] - self.padding.sum(axis));
+        }
+        inner_pos[axis] -= self.scroll_offset[axis];
+      }
+    }
+    let c_clipping = self.child_clipping(clipping, pos, size);
+
+    let mut curr_index = 0;
+    let mut used = [0.0; 2];
+    if let Layout::Grid { .. } = self.children_layout {
+      let (rects_created, g_used) = self.layout_grid(rect_buffer, inner_pos, inner_size, layer, c_clipping, ctx)?;
+      curr_index = rects_created;
+      used = g_used;
+    } else if let Layout::Stack = self.children_layout {
+      let (rects_created, s_used) = self.layout_stack(rect_buffer, inner_pos, inner_size, layer, c_clipping, ctx)?;
+      curr_index = rects_created;
+      used = s_used;
+    } else if self.children_layout.wraps() {
+      // Lay out each line in turn, spaced out across the layout axis.
+      let lines = self.break_lines(inner_size, ctx)?;
+      let lines_size = lines.iter().map(|l| l.1).sum::<f32>() +
+        self.line_gap * lines.len().saturating_sub(1) as f32;
+      let (lines_start, lines_between) =
+        self.align_lines.offsets((inner_size[cross] - lines_size).max(0.0), lines.len());
+      let mut cross_used = lines_start;
+      for (ii, &(ref range, line_size)) in lines.iter().enumerate() {
+        if ii > 0 {
+          cross_used += self.line_gap + lines_between;
+        }
+        let mut l_pos = inner_pos;
+        let mut l_size = inner_size;
+        l_pos[cross] += cross_used;
+        l_size[cross] = line_size;
+        let (rects_created, l_used) =
+          self.layout_line(range.clone(), &mut rect_buffer[curr_index..], l_pos, l_size, layer, c_clipping, ctx)?;
+        curr_index += rects_created;
+        used[main] = l_used[main].max(used[main]);
+        cross_used += line_size;
+      }
+      used[cross] = cross_used;
+    } else {
+      let (rects_created, l_used) = self.layout_line(0..tree.children(self).len(), rect_buffer, inner_pos, inner_size, layer, c_clipping, ctx)?;
+      curr_index = rects_created;
+      used = l_used;
+    }
+    if tree.children(self).any(|c| c.is_anchored()) {
+      self.layout_anchored(&mut rect_buffer[..curr_index], pos, size, layer, c_clipping, ctx)?;
     }
-  }
 
-  pub fn add_child(&mut self, child: Node) {
-    self.children.push(child);
-  }
-  pub fn add_children(&mut self, mut children: Vec<Node>) {
-    self.children.append(&mut children);
+    // Add self to the buffer, cut off by any clipping ancestors.
+    let (r_pos, r_size) = match clipping.cut {
+      Some(cut) => intersect((pos, size), cut),
+      None => (pos, size),
+    };
+    let rect = Rect {
+      id: self.id,
+      pos: r_pos,
+      size: r_size,
+      content_size: [size[0].max(used[0] + self.padding.sum(0)),
+                     size[1].max(used[1] + self.padding.sum(1))],
+      layer,
+      clip: clipping.clip,
+    };
+    write_rect(&mut rect_buffer[curr_index], rect);
+    self.cache.set(Some(Cached { input, rect, count: curr_index + 1, layout: ctx.layout }));
+    self.dirty.set(false);
+    Ok(curr_index + 1)
   }
 
-  /// Creates a buffer of Rect structs to be used when laying out.
-  pub fn alloc_rect_buffer(&self) -> Vec<Rect> {
-    let mut buf = Vec::with_capacity(self.children.len() + 1);
-    for c in &self.children {
-      let mut other_children = c.alloc_rect_buffer();
-      buf.append(&mut other_children);
+  /// Puts back the rects of this clean node tree from its last layout, only
+  /// writing those the buffer no longer has. The tree's nodes must not have
+  /// been laid out since `layout`, the number of the layout that last laid
+  /// out its root.
+  /// # Returns
+  /// False, with the buffer partly written, if any node in the tree has
+  /// changed or been laid out on its own since, so has to be laid out again.
+  fn restore(&self, rect_buffer: &mut [Rect], layout: usize, tree: Tree) -> bool {
+    let cached = match self.cache.get() {
+      Some(cached) if !self.dirty.get() && cached.layout <= layout && cached.count <= rect_buffer.len() => cached,
+      _ => return false,
+    };
+    let mut curr_index = 0;
+    for c in tree.children(self) {
+      if !c.restore(&mut rect_buffer[curr_index..], layout, tree) {
+        return false;
+      }
+      curr_index += c.cache.get().map_or(0, |c| c.count);
     }
-    buf.push(Rect::new(self.id));
-    return buf;
+    if curr_index + 1 != cached.count {
+      return false;
+    }
+    if rect_buffer[curr_index] != cached.rect {
+      write_rect(&mut rect_buffer[curr_index], cached.rect);
+    }
+    true
   }
 
-  /// Layout this node tree, storing final rectangles in the given buffer of rects. 
-  /// # Params
-  /// * `rect_buffer` - A buffer of rectangles to avoid repeated allocations on
-  ///                   many layouts per frame. Use alloc_rect_buffer() to
-  ///                   create a buffer of the correct size.
-  /// * `x` - The x position of the final node tree
-  /// * `y` - The y position of the final node tree
-  /// * `w` - The width given to layout the nodes.
-  /// * `h` - The height given to layout the nodes.
-  /// * `layer` - The lowest z index layer this node should reside in.
-  ///             Children's z indexes will be increased by 1 for each 'layer'
-  ///             in the tree.
+  /// Sizes a list of lengths laid out along one axis of this node, each with
+  /// an optional min and max bound. Absolute lengths are taken from the
+  /// `available` space first, along with `spacing` for gaps and margins, and
+  /// relative and flexible lengths share what's left. Auto lengths should
+  /// already have been measured. If they don't fit, `overflow` says what to
+  /// do.
   /// # Returns
-  /// The number of rectangles returned. This is mainly for recursive calls
-  /// into node tree children.
-  /// # Panics
-  /// ## In debug build
-  /// * If layout isn't large enough to account for absolutely sized components.
-  /// * If provided rect_buffer isn't large enough to accommodate for all final rectangles.
-  pub fn layout(&self, rect_buffer: &mut [Rect], x: f32, y: f32, w: f32, h: f32, layer: f32) -> usize {
-    let mut curr_index = 0;
-    // First, count up free space to split between relative components, and the
+  /// The size of each length, and the free space left over.
+  fn resolve_lengths(&self, lengths: &[(DynLen, Option<f32>, Option<f32>)], available: f32, spacing: f32, overflow: Overflow) -> Result<(Vec<f32>, f32), LayoutError> {
+    // First, size the absolute (and percentage) lengths, and count up the
     // total sum of relative proportions (to use when calculating the ratio)
-    let mut free_space = 
-      match self.children_layout {
-        Layout::Horizontal => w,
-        Layout::Vertical => h,
-      };
+    // and the least space the relative and flexible lengths can shrink to.
+    let mut sizes = Vec::with_capacity(lengths.len());
+    let mut rel_items = Vec::new();
+    let mut abs_size = 0.0;
+    let mut flex_size = 0.0;
     let mut ratio_size = 0.0;
-    for c in &self.children {
-      match c.size {
-        DynLen::Absolute(l) => free_space -= l,
-        DynLen::Relative(l) => ratio_size += l,
+    let mut rel_count = 0;
+    for &(len, min, max) in lengths {
+      match len {
+        DynLen::Relative(l) => {
+          ratio_size += l;
+          rel_count += 1;
+          flex_size += min.unwrap_or(0.0);
+          rel_items.push(RelItem::new(l, min, max));
+          sizes.push(0.0);
+        }
+        DynLen::Flex { basis, grow, shrink } => {
+          flex_size += if shrink > 0.0 { min.unwrap_or(0.0) } else { clamp_len(basis, min, max) };
+          rel_items.push(RelItem::flex(basis, grow, shrink, min, max));
+          sizes.push(0.0);
+        }
+        DynLen::Absolute(_) | DynLen::Percent(_) | DynLen::Auto => {
+          let l = clamp_len(len.fixed(available).unwrap_or(0.0), min, max);
+          abs_size += l;
+          sizes.push(l);
+        }
+      }
+    }
+
+    // Whatever's left is free space to split between relative and flexible
+    // lengths.
+    let mut free_space = available - abs_size - spacing;
+    if free_space < flex_size {
+      match overflow {
+        Overflow::Deny =>
+          return Err(LayoutError::InsufficientSpace {
+            id: self.id, required: abs_size + flex_size + spacing, available }),
+        Overflow::Shrink if abs_size > 0.0 => {
+          let scale = (available - flex_size - spacing).max(0.0) / abs_size;
+          for (&(len, min, _), size) in lengths.iter().zip(sizes.iter_mut()) {
+            match len {
+              DynLen::Relative(_) | DynLen::Flex { .. } => (),
+              _ => *size = clamp_len(*size * scale, min, None),
+            }
+          }
+        }
+        Overflow::Shrink | Overflow::Visible | Overflow::Clip | Overflow::Scroll => (),
       }
+      free_space = flex_size;
+    }
+    if rel_count > 0 && ratio_size <= 0.0 {
+      return Err(LayoutError::ZeroRelativeRatio { id: self.id, ratio_sum: ratio_size });
     }
 
-    debug_assert!(free_space > 0.0, "Not enough free space to fit in all the absolute components in layout.");
+    // Split the free space between relative and flexible lengths, then copy
+    // their sizes back in order.
+    resolve_relative(&mut rel_items, free_space);
+    let mut rel_sizes = rel_items.iter().map(|r| r.size);
+    for (&(len, _, _), size) in lengths.iter().zip(sizes.iter_mut()) {
+      if let DynLen::Relative(_) | DynLen::Flex { .. } = len {
+        *size = rel_sizes.next().unwrap();
+        free_space -= *size;
+      }
+    }
+    Ok((sizes, free_space.max(0.0)))
+  }
 
-    // Add children to layed out rectangles
-    // Keep track of space used laying out components for x / y positions
-    let mut size_used = 0.0;
-    for c in &self.children {
-      debug_assert!(curr_index < rect_buffer.len(), "Layout rect buffer overflow.");
-      // Calculate the size to give this child.
-      let (c_x, c_y, c_w, c_h);
-      match self.children_layout {
-        Layout::Horizontal => {
-          match c.size {
-            DynLen::Absolute(l) => { c_w = l; c_h = h; }
-            DynLen::Relative(l) => { c_w = free_space * l/ratio_size; c_h = h; }
+  /// Finds the first column and row, and the number of columns and rows, of
+  /// the cells covered by each flow child in a grid layout. Children without
+  /// a cell fill the grid in order, a row at a time.
+  fn grid_cells(&self, tree: Tree) -> Result<Vec<CellSpan>, LayoutError> {
+    let (columns, rows) = match self.children_layout {
+      Layout::Grid { ref columns, ref rows } => (columns, rows),
+      _ => return Ok(Vec::new()),
+    };
+    let cols = columns.len().max(1);
+    let mut cells = Vec::with_capacity(tree.children(self).len());
+    for (placed, c) in tree.children(self).filter(|c| !c.is_anchored()).enumerate() {
+      let cell = c.grid_cell.unwrap_or_else(|| GridCell::new(placed / cols, placed % cols));
+      if cell.column + cell.column_span.max(1) > columns.len() || cell.row + cell.row_span.max(1) > rows.len() {
+        return Err(LayoutError::GridCellOutOfRange {
+          id: c.id, row: cell.row, column: cell.column, rows: rows.len(), columns: columns.len() });
+      }
+      cells.push(([cell.column, cell.row], [cell.column_span.max(1), cell.row_span.max(1)]));
+    }
+    Ok(cells)
+  }
+
+  /// Lays out this node's children into the cells of its grid, inside the
+  /// given bounds.
+  /// # Returns
+  /// The number of rectangles written to the buffer, and the width and height
+  /// taken up by the tracks.
+  fn layout_grid(&self, rect_buffer: &mut [Rect], pos: [f32; 2], size: [f32; 2], layer: f32, clipping: Clipping, ctx: &mut LayoutCtx) -> Result<(usize, [f32; 2]), LayoutError> {
+    let (columns, rows) = match self.children_layout {
+      Layout::Grid { ref columns, ref rows } => (columns, rows),
+      _ => return Ok((0, [0.0; 2])),
+    };
+
+    let tree = ctx.tree;
+    let cells = self.grid_cells(tree)?;
+
+    // Auto tracks are as big as the largest child measured in them, counting
+    // only children covering that one track.
+    let defs = [columns, rows];
+    let mut measured = [vec![0.0f32; columns.len()], vec![0.0f32; rows.len()]];
+    if columns.iter().chain(rows.iter()).any(|&l| l == DynLen::Auto) {
+      for (c, &(start, span)) in tree.children(self).filter(|c| !c.is_anchored()).zip(cells.iter()) {
+        let c_size = c.measure_content(size, ctx)?;
+        for axis in 0..2 {
+          if span[axis] == 1 && defs[axis][start[axis]] == DynLen::Auto {
+            let track = &mut measured[axis][start[axis]];
+            *track = track.max(c_size[axis] + c.margin.sum(axis));
           }
-          size_used += c_w;
         }
-        Layout::Vertical => {
-          match c.size {
-            DynLen::Absolute(l) => { c_w = w; c_h = l; }
-            DynLen::Relative(l) => { c_w = w; c_h = free_space * l/ratio_size; }
+      }
+    }
+
+    // Size the tracks along each axis, and find where each one starts.
+    let mut tracks = [Vec::new(), Vec::new()];
+    let mut used = [0.0; 2];
+    for (axis, &gap) in [self.gap, self.line_gap].iter().enumerate() {
+      let lengths: Vec<_> = defs[axis].iter().zip(measured[axis].iter())
+        .map(|(&l, &m)| (l.or_measured(m), None, None)).collect();
+      let spacing = gap * lengths.len().saturating_sub(1) as f32;
+      let (sizes, _) = self.resolve_lengths(&lengths, size[axis], spacing, self.overflow)?;
+      for (ii, len) in sizes.into_iter().enumerate() {
+        if ii > 0 {
+          used[axis] += gap;
+        }
+        tracks[axis].push((pos[axis] + used[axis], len));
+        used[axis] += len;
+      }
+    }
+
+    let mut curr_index = 0;
+    let mut cells = cells.into_iter();
+    for c in tree.children(self) {
+      // Anchored children are laid out afterwards, leave room for them.
+      if c.is_anchored() {
+        curr_index += c.rect_count(tree);
+        continue;
+      }
+
+      // The child fills the cells it spans, inside its margin.
+      let (start, span) = cells.next().unwrap();
+      let mut c_pos = [0.0; 2];
+      let mut c_size = [0.0; 2];
+      for axis in 0..2 {
+        let first = tracks[axis][start[axis]];
+        let last = tracks[axis][start[axis] + span[axis] - 1];
+        c_pos[axis] = first.0 + c.margin.start(axis);
+        c_size[axis] = (last.0 + last.1 - first.0 - c.margin.sum(axis)).max(0.0);
+      }
+      c_pos[0] = self.directed(c_pos[0], c_size[0], pos[0], size[0]);
+
+      // Add child's rectangles to the list
+      let rects_created = c.layout_node(&mut rect_buffer[curr_index..], c_pos, c_size, layer + 1.0, clipping, ctx)?;
+      curr_index += rects_created;
+    }
+    Ok((curr_index, used))
+  }
+
+  /// Lays out the anchored children of this node, once the others have been
+  /// laid out around the gaps left for them in the rect buffer. They go in a
+  /// layer above everything else.
+  fn layout_anchored(&self, rect_buffer: &mut [Rect], pos: [f32; 2], size: [f32; 2], layer: f32, clipping: Clipping, ctx: &mut LayoutCtx) -> Result<(), LayoutError> {
+    let tree = ctx.tree;
+    let counts: Vec<usize> = tree.children(self).map(|c| c.rect_count(tree)).collect();
+    let mut c_layer = layer + 1.0;
+    let mut curr_index = 0;
+    for (c, &count) in tree.children(self).zip(counts.iter()) {
+      if !c.is_anchored() {
+        for r in &rect_buffer[curr_index..curr_index + count] {
+          c_layer = c_layer.max(r.layer + 1.0);
+        }
+      }
+      curr_index += count;
+    }
+
+    let main = self.children_layout.main_axis();
+    let mut curr_index = 0;
+    for (c, &count) in tree.children(self).zip(counts.iter()) {
+      if let Position::Anchored(ref anchors) = c.position {
+        let measured = c.measure_auto(size, main, ctx)?;
+        let mut c_pos = [0.0; 2];
+        let mut c_size = [0.0; 2];
+        let edges = [(anchors.left, anchors.right), (anchors.top, anchors.bottom)];
+        for axis in 0..2 {
+          let offset = |l: Option<DynLen>| l.map(|l| match l {
+            DynLen::Relative(l) => l * size[axis],
+            DynLen::Flex { basis, .. } => basis,
+            DynLen::Absolute(_) | DynLen::Percent(_) | DynLen::Auto => l.fixed(size[axis]).unwrap_or(0.0),
+          });
+          let (start, end) = (offset(edges[axis].0), offset(edges[axis].1));
+          let fill = (size[axis] - start.unwrap_or(0.0) - end.unwrap_or(0.0)).max(0.0);
+          let len = if axis == main { Some(c.size) } else { c.cross_size };
+          c_size[axis] = len.and_then(|l| l.or_measured(measured[axis]).fixed(size[axis])).unwrap_or(fill);
+          if axis == main {
+            c_size[axis] = clamp_len(c_size[axis], c.min_size, c.max_size);
           }
-          size_used += c_h;
+          c_pos[axis] = pos[axis] + match (start, end) {
+            (Some(start), _) => start,
+            (None, Some(end)) => size[axis] - end - c_size[axis],
+            (None, None) => 0.0,
+          };
         }
+        c.layout_node(&mut rect_buffer[curr_index..], c_pos, c_size, c_layer, clipping, ctx)?;
+      }
+      curr_index += count;
+    }
+    Ok(())
+  }
+
+  /// Lays out this node's children on top of each other inside the given
+  /// bounds. Each child starts a layer above the highest layer used by the
+  /// children before it, so later children are drawn over earlier ones.
+  /// # Returns
+  /// The number of rectangles written to the buffer, and the width and height
+  /// taken up by the largest child.
+  fn layout_stack(&self, rect_buffer: &mut [Rect], pos: [f32; 2], size: [f32; 2], layer: f32, clipping: Clipping, ctx: &mut LayoutCtx) -> Result<(usize, [f32; 2]), LayoutError> {
+    let mut curr_index = 0;
+    let mut used: [f32; 2] = [0.0; 2];
+    let mut c_layer = layer + 1.0;
+    let tree = ctx.tree;
+    for c in tree.children(self) {
+      // Anchored children are laid out afterwards, leave room for them.
+      if c.is_anchored() {
+        curr_index += c.rect_count(tree);
+        continue;
+      }
+
+      let space = [(size[0] - c.margin.sum(0)).max(0.0), (size[1] - c.margin.sum(1)).max(0.0)];
+      let measured = c.measure_auto(space, 0, ctx)?;
+      let c_size = [
+        clamp_len(c.size.or_measured(measured[0]).fixed(size[0]).unwrap_or(space[0]), c.min_size, c.max_size),
+        c.cross_size.and_then(|l| l.or_measured(measured[1]).fixed(size[1])).unwrap_or(space[1]),
+      ];
+      let c_pos = [
+        self.directed(pos[0] + c.margin.left + self.justify.offsets(space[0] - c_size[0], 1).0, c_size[0], pos[0], size[0]),
+        pos[1] + c.margin.top + c.align_self.unwrap_or(self.cross_align).offset(space[1] - c_size[1]),
+      ];
+      for axis in 0..2 {
+        used[axis] = used[axis].max(c_size[axis] + c.margin.sum(axis));
+      }
+
+      // Add child's rectangles to the list, and start the next child above
+      // all of them.
+      let rects_created = c.layout_node(&mut rect_buffer[curr_index..], c_pos, c_size, c_layer, clipping, ctx)?;
+      for r in &rect_buffer[curr_index..curr_index + rects_created] {
+        c_layer = c_layer.max(r.layer + 1.0);
+      }
+      curr_index += rects_created;
+    }
+    Ok((curr_index, used))
+  }
+
+  /// Splits this node's children into lines for a wrapping layout, starting a
+  /// new line whenever the next child wouldn't fit in `available` along the
+  /// layout axis, given the size of the node inside its padding. Relative
+  /// children are counted at their min size, and
+  /// anchored children join whichever line they come in.
+  /// # Returns
+  /// The range of children in each line, along with the line's size across
+  /// the layout axis: that of its largest child with an absolute cross size.
+  /// Children with a percentage cross size take that percentage of their
+  /// line.
+  fn break_lines(&self, size: [f32; 2], ctx: &mut LayoutCtx) -> Result<Vec<(Range<usize>, f32)>, LayoutError> {
+    let main = self.children_layout.main_axis();
+    let cross = 1 - main;
+    let available = size[main] - self.leading_gap - self.trailing_gap;
+    let tree = ctx.tree;
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut line_len = 0;
+    let mut line_used = 0.0;
+    let mut line_size: f32 = 0.0;
+    for (ii, c) in tree.children(self).enumerate() {
+      // Anchored children don't take up space, so just go in the current line.
+      if c.is_anchored() {
+        continue;
       }
+      let measured = c.measure_auto(size, main, ctx)?;
+      let c_main = c.margin.sum(main) + match c.size.or_measured(measured[main]) {
+        DynLen::Flex { basis, .. } => clamp_len(basis, c.min_size, c.max_size),
+        len => match len.fixed(size[main]) {
+          Some(l) => clamp_len(l, c.min_size, c.max_size),
+          None => c.min_size.unwrap_or(0.0),
+        },
+      };
+      let c_cross = c.margin.sum(cross) + match c.cross_size.map(|l| l.or_measured(measured[cross])) {
+        Some(DynLen::Absolute(l)) => l,
+        Some(DynLen::Relative(_)) | Some(DynLen::Percent(_)) | Some(DynLen::Auto) | Some(DynLen::Flex { .. }) | None => 0.0,
+      };
+      if line_len > 0 && line_used + self.gap + c_main > available {
+        lines.push((start..ii, line_size));
+        start = ii;
+        line_len = 0;
+        line_used = 0.0;
+        line_size = 0.0;
+      }
+      line_used += if line_len > 0 { self.gap + c_main } else { c_main };
+      line_size = line_size.max(c_cross);
+      line_len += 1;
+    }
+    if start < tree.children(self).len() {
+      lines.push((start..tree.children(self).len(), line_size));
+    }
+    Ok(lines)
+  }
+
+  /// Lays out a range of this node's children in a single line along the
+  /// layout axis, inside the given bounds.
+  /// # Returns
+  /// The number of rectangles written to the buffer, and the width and height
+  /// taken up by the children (including gaps and margins).
+  #[allow(clippy::too_many_arguments)]
+  fn layout_line(&self, range: Range<usize>, rect_buffer: &mut [Rect], pos: [f32; 2], size: [f32; 2], layer: f32, clipping: Clipping, ctx: &mut LayoutCtx) -> Result<(usize, [f32; 2]), LayoutError> {
+    let mut curr_index = 0;
+    let main = self.children_layout.main_axis();
+    let cross = 1 - main;
+    let available = size[main];
+    let tree = ctx.tree;
+    let children = tree.children(self).skip(range.start).take(range.len());
+
+    // Count up the space taken by gaps and margins, then size the children.
+    // Anchored children are left out of this, and laid out afterwards.
+    let flow = || children.clone().filter(|c| !c.is_anchored());
+    let n = flow().count();
+    let mut spacing =
+      if n == 0 { 0.0 }
+      else { self.leading_gap + self.trailing_gap + self.gap * (n - 1) as f32 };
+    for c in flow() {
+      spacing += c.margin.sum(main);
+    }
+    let mut measured = Vec::with_capacity(n);
+    for c in flow() {
+      measured.push(c.measure_auto([(size[0] - c.margin.sum(0)).max(0.0), (size[1] - c.margin.sum(1)).max(0.0)], main, ctx)?);
+    }
+    let lengths: Vec<_> = flow().zip(measured.iter())
+      .map(|(c, m)| (c.size.or_measured(m[main]), c.min_len(main), c.max_size)).collect();
+    // A child too big for a line of a wrapping layout gets the line to itself,
+    // and overflows it.
+    let overflow = match self.overflow {
+      Overflow::Deny if self.children_layout.wraps() && n == 1 => Overflow::Visible,
+      overflow => overflow,
+    };
+    let (sizes, free_space) = self.resolve_lengths(&lengths, available, spacing, overflow)?;
 
-      // Calculate the position to give this child.
-      match self.children_layout {
-        Layout::Horizontal => { c_x = x + size_used - c_w; c_y = y; }
-        Layout::Vertical => { c_x = x; c_y = y + size_used - c_h; }
+    // Free space the children don't take up is placed by the justify mode.
+    let (justify_start, justify_between) = self.justify.offsets(free_space, n);
+
+    // Add children to layed out rectangles
+    // Keep track of space used laying out components (including gaps and
+    // margins) for x / y positions
+    let mut used = [0.0; 2];
+    let mut sizes = sizes.into_iter().zip(measured);
+    let mut first = true;
+    for c in children {
+      // Anchored children are laid out afterwards, leave room for them.
+      if c.is_anchored() {
+        curr_index += c.rect_count(tree);
+        continue;
       }
 
+      // Calculate the position and size to give this child, inside its margin.
+      let (len, measured) = sizes.next().unwrap();
+      let mut c_pos = [0.0; 2];
+      let mut c_size = [0.0; 2];
+      used[main] += if first { self.leading_gap + justify_start } else { self.gap + justify_between };
+      first = false;
+      used[main] += c.margin.start(main);
+      c_pos[main] = pos[main] + used[main];
+      c_size[main] = len;
+      let cross_space = (size[cross] - c.margin.sum(cross)).max(0.0);
+      c_size[cross] = match (c.aspect_ratio, c.cross_size) {
+        // Now the size is known, an auto cross size can follow it.
+        (Some(aspect), Some(DynLen::Auto)) => aspect.len(cross, len),
+        _ => c.cross_size.and_then(|l| l.or_measured(measured[cross]).fixed(size[cross])).unwrap_or(cross_space),
+      };
+      c_pos[cross] = pos[cross] + c.margin.start(cross) +
+        c.align_self.unwrap_or(self.cross_align).offset(cross_space - c_size[cross]);
+      used[cross] = used[cross].max(c_size[cross] + c.margin.sum(cross));
+      used[main] += len + c.margin.end(main);
+      c_pos[main] = self.directed(c_pos[main], len, pos[main], size[main]);
+
       // Add child's rectangles to the list
-      let rects_created = c.layout(&mut rect_buffer[curr_index..], c_x, c_y, c_w, c_h, layer + 1.0);
+      let rects_created = c.layout_node(&mut rect_buffer[curr_index..], c_pos, c_size, layer + 1.0, clipping, ctx)?;
       curr_index += rects_created;
     }
-    // Add self to the buffer.
-    debug_assert!(curr_index < rect_buffer.len(), "Layout rect buffer overflow.");
-    rect_buffer[curr_index].id = self.id;
-    rect_buffer[curr_index].pos[0] = x;
-    rect_buffer[curr_index].pos[1] = y;
-    rect_buffer[curr_index].size[0] = w;
-    rect_buffer[curr_index].size[1] = h;
-    rect_buffer[curr_index].layer = layer;
-    return curr_index + 1;
+    if n > 0 {
+      used[main] += self.trailing_gap;
+    }
+    Ok((curr_index, used))
+  }
+}
+
+/// Clamps a length between optional bounds. The min bound wins if they cross.
+fn clamp_len(l: f32, min: Option<f32>, max: Option<f32>) -> f32 {
+  let l = max.map_or(l, |max| l.min(max));
+  min.map_or(l, |min| l.max(min))
+}
+
+/// A relatively sized or flexible child, used when splitting free space.
+#[derive(Debug, Clone)]
+struct RelItem {
+  basis: f32,
+  grow: f32,
+  shrink: f32,
+  min: f32,
+  max: f32,
+  /// The resolved size, written by resolve_relative().
+  size: f32,
+}
+
+impl RelItem {
+  /// A relative length, which grows from nothing by its ratio.
+  fn new(ratio: f32, min: Option<f32>, max: Option<f32>) -> RelItem {
+    RelItem::flex(0.0, ratio, 0.0, min, max)
+  }
+
+  fn flex(basis: f32, grow: f32, shrink: f32, min: Option<f32>, max: Option<f32>) -> RelItem {
+    RelItem {
+      basis, grow, shrink, min: min.unwrap_or(0.0), max: max.unwrap_or(f32::INFINITY), size: 0.0,
+    }
+  }
+
+  /// The basis clamped to the min and max size.
+  fn hypothetical(&self) -> f32 {
+    self.basis.min(self.max).max(self.min)
+  }
+}
+
+/// Splits free space between relatively sized and flexible items, clamping
+/// each to its min and max size, the way flexbox resolves flexible lengths.
+/// If the items' bases fit, each grows from its basis by its share of the
+/// space left by their grow factors, otherwise each shrinks by its share of
+/// the space missing by its shrink factor times its basis. Items that can't
+/// flex that way keep their basis. Space taken or given back by clamping is
+/// handed to the unclamped items: each round, if the clamping added space in
+/// total the items that hit their min are frozen, if it removed space those
+/// that hit their max are, and the rest are split again.
+fn resolve_relative(items: &mut [RelItem], free_space: f32) {
+  let growing = items.iter().map(|i| i.hypothetical()).sum::<f32>() <= free_space;
+  let factor = |item: &RelItem| if growing { item.grow } else { item.shrink * item.basis };
+  let mut frozen = Vec::with_capacity(items.len());
+  for item in items.iter_mut() {
+    item.size = item.hypothetical();
+    frozen.push(factor(item) <= 0.0 ||
+                (growing && item.basis > item.size) ||
+                (!growing && item.basis < item.size));
+  }
+  loop {
+    let mut remaining = free_space;
+    let mut factor_size = 0.0;
+    for (item, &f) in items.iter().zip(frozen.iter()) {
+      if f { remaining -= item.size; } else { remaining -= item.basis; factor_size += factor(item); }
+    }
+    if !frozen.contains(&false) {
+      return;
+    }
+
+    // Split what's left and measure how much clamping moved things.
+    let target = |item: &RelItem| item.basis + if factor_size > 0.0 { remaining * factor(item) / factor_size } else { 0.0 };
+    let mut violation = 0.0;
+    for (item, &f) in items.iter_mut().zip(frozen.iter()) {
+      if f { continue; }
+      let target = target(item);
+      item.size = target.min(item.max).max(item.min);
+      violation += item.size - target;
+    }
+
+    for (item, f) in items.iter().zip(frozen.iter_mut()) {
+      if *f { continue; }
+      let target = target(item);
+      *f = if violation > 0.0 { item.size > target }
+           else if violation < 0.0 { item.size < target }
+           else { true };
+    }
   }
 }
 
 /// A rectangle with a defined size in space. Created from laying out nodes.
 /// These can be drawn, and will be correctly layed out.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Rect {
   pub id: u32,
   pub pos: [f32; 2],
+  /// Visible size of this rect.
   pub size: [f32; 2],
+  /// Size of the content laid out inside this rect. Larger than `size` if the
+  /// children overflow.
+  pub content_size: [f32; 2],
   /// Z index this rect resides in.
   pub layer: f32,
+  /// Position and size of the area this rect is visible in: the overlap of
+  /// the rects of all its clipping ancestors, or None if it has none.
+  /// Renderers should scissor the rect to it.
+  pub clip: Option<([f32; 2], [f32; 2])>,
 }
 
 impl Rect {
   fn new(id: u32) -> Rect {
     Rect {
-      id: id, pos: [0.0, 0.0], size: [0.0, 0.0], layer: 0.0,
+      id, pos: [0.0, 0.0], size: [0.0, 0.0], content_size: [0.0, 0.0], layer: 0.0, clip: None,
     }
   }
 }
 
+/// The overlap of two rects given by position and size, with a size of 0
+/// where they don't overlap.
+fn intersect(a: ([f32; 2], [f32; 2]), b: ([f32; 2], [f32; 2])) -> ([f32; 2], [f32; 2]) {
+  let mut pos = [0.0; 2];
+  let mut size = [0.0; 2];
+  for axis in 0..2 {
+    let start = a.0[axis].max(b.0[axis]);
+    let end = (a.0[axis] + a.1[axis]).min(b.0[axis] + b.1[axis]).max(start);
+    pos[axis] = start;
+    size[axis] = end - start;
+  }
+  (pos, size)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  fn resolve(items: &[(f32, Option<f32>, Option<f32>)], free_space: f32) -> Vec<f32> {
+    let mut items: Vec<RelItem> = items.iter().map(|&(r, min, max)| RelItem::new(r, min, max)).collect();
+    resolve_relative(&mut items, free_space);
+    items.iter().map(|i| i.size).collect()
+  }
+
+  #[test]
+  fn relative_split_by_ratio() {
+    assert_eq!(resolve(&[(1.0, None, None), (2.0, None, None), (1.0, None, None)], 400.0),
+               vec![100.0, 200.0, 100.0]);
+  }
+
+  #[test]
+  fn relative_max_gives_space_back() {
+    assert_eq!(resolve(&[(1.0, None, Some(50.0)), (1.0, None, None), (2.0, None, None)], 400.0),
+               vec![50.0, 350.0 / 3.0, 700.0 / 3.0]);
+  }
+
+  #[test]
+  fn relative_min_takes_space() {
+    assert_eq!(resolve(&[(1.0, Some(150.0), None), (3.0, None, None)], 400.0),
+               vec![150.0, 250.0]);
+  }
+
+  #[test]
+  fn relative_clamping_cascades() {
+    // Clamping the first item to its max frees space that pushes the second
+    // over its own max in the next round.
+    assert_eq!(resolve(&[(1.0, None, Some(50.0)), (1.0, None, Some(250.0)), (1.0, None, None)], 600.0),
+               vec![50.0, 250.0, 300.0]);
+  }
+
+  #[test]
+  fn relative_mins_deny_overflow() {
+    let mut row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    for id in 2..4 {
+      let mut c = Node::new(id, Layout::Vertical, DynLen::Relative(1.0));
+      c.set_min_size(Some(300.0));
+      row.add_child(c);
+    }
+    let mut rects = row.alloc_rect_buffer();
+    assert_eq!(row.layout(&mut rects, 0.0, 0.0, 100.0, 50.0, 0.0),
+               Err(LayoutError::InsufficientSpace { id: 1, required: 600.0, available: 100.0 }));
+    row.set_overflow(Overflow::Visible);
+    row.layout(&mut rects, 0.0, 0.0, 100.0, 50.0, 0.0).unwrap();
+    assert_eq!(rects[1].pos, [300.0, 0.0]);
+    assert_eq!(rects[1].size, [300.0, 50.0]);
+  }
+
+  #[test]
+  fn relative_min_beats_max() {
+    assert_eq!(resolve(&[(1.0, Some(300.0), Some(100.0)), (1.0, None, None)], 400.0),
+               vec![300.0, 100.0]);
+  }
+
+  #[test]
+  fn relative_mins_exceed_free_space() {
+    assert_eq!(resolve(&[(1.0, Some(100.0), None), (1.0, Some(100.0), None)], 50.0),
+               vec![100.0, 100.0]);
+  }
+
+  #[test]
+  fn sidebar_bounded_relative() {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    let mut sidebar = Node::new(2, Layout::Vertical, DynLen::Relative(1.0));
+    sidebar.set_min_size(Some(150.0));
+    sidebar.set_max_size(Some(400.0));
+    root.add_children(vec![sidebar, Node::new(3, Layout::Vertical, DynLen::Relative(3.0))]);
+    let mut rects = root.alloc_rect_buffer();
+
+    root.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[0].size, [150.0, 100.0]);
+    assert_eq!(rects[1].pos, [150.0, 0.0]);
+    assert_eq!(rects[1].size, [250.0, 100.0]);
+
+    root.layout(&mut rects, 0.0, 0.0, 2000.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[0].size, [400.0, 100.0]);
+    assert_eq!(rects[1].size, [1600.0, 100.0]);
+  }
+
+  #[test]
+  fn padding_and_margin() {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    root.set_padding(Sides::all(10.0));
+    let mut left = Node::new(2, Layout::Vertical, DynLen::Absolute(100.0));
+    left.set_margin(Sides::new(0.0, 5.0, 20.0, 0.0));
+    root.add_children(vec![left, Node::new(3, Layout::Vertical, DynLen::Relative(1.0))]);
+    let mut rects = root.alloc_rect_buffer();
+
+    root.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[0].pos, [10.0, 15.0]);
+    assert_eq!(rects[0].size, [100.0, 75.0]);
+    assert_eq!(rects[1].pos, [130.0, 10.0]);
+    assert_eq!(rects[1].size, [260.0, 80.0]);
+    assert_eq!(rects[2].size, [400.0, 100.0]);
+  }
+
+  #[test]
+  fn gaps_between_children() {
+    let mut root = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    root.set_gap(8.0);
+    root.set_outer_gaps(4.0, 2.0);
+    root.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(40.0)),
+                           Node::new(3, Layout::Vertical, DynLen::Absolute(40.0)),
+                           Node::new(4, Layout::Vertical, DynLen::Relative(1.0))]);
+    let mut rects = root.alloc_rect_buffer();
+
+    assert_eq!(root.layout(&mut rects, 0.0, 0.0, 100.0, 200.0, 0.0), Ok(4));
+    assert_eq!(rects[0].pos, [0.0, 4.0]);
+    assert_eq!(rects[1].pos, [0.0, 52.0]);
+    assert_eq!(rects[2].pos, [0.0, 100.0]);
+    assert_eq!(rects[2].size, [100.0, 98.0]);
+  }
+
+  #[test]
+  fn cross_axis_alignment() {
+    let mut toolbar = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    toolbar.set_cross_align(Align::Center);
+    let mut button = Node::new(2, Layout::Vertical, DynLen::Absolute(80.0));
+    button.set_cross_size(Some(DynLen::Absolute(40.0)));
+    let mut end_button = button.clone();
+    end_button.id = 3;
+    end_button.set_align_self(Some(Align::End));
+    toolbar.add_children(vec![button, end_button, Node::new(4, Layout::Vertical, DynLen::Relative(1.0))]);
+    let mut rects = toolbar.alloc_rect_buffer();
+
+    toolbar.layout(&mut rects, 0.0, 0.0, 400.0, 200.0, 0.0).unwrap();
+    assert_eq!(rects[0].pos, [0.0, 80.0]);
+    assert_eq!(rects[0].size, [80.0, 40.0]);
+    assert_eq!(rects[1].pos, [80.0, 160.0]);
+    assert_eq!(rects[2].pos, [160.0, 0.0]);
+    assert_eq!(rects[2].size, [240.0, 200.0]);
+  }
+
+  #[test]
+  fn justify_absolute_children() {
+    let positions = |justify| {
+      let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+      root.set_justify(justify);
+      root.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(20.0)),
+                             Node::new(3, Layout::Vertical, DynLen::Absolute(20.0))]);
+      let mut rects = root.alloc_rect_buffer();
+      root.layout(&mut rects, 0.0, 0.0, 100.0, 10.0, 0.0).unwrap();
+      (rects[0].pos[0], rects[1].pos[0])
+    };
+    assert_eq!(positions(Justify::Start), (0.0, 20.0));
+    assert_eq!(positions(Justify::End), (60.0, 80.0));
+    assert_eq!(positions(Justify::Center), (30.0, 50.0));
+    assert_eq!(positions(Justify::SpaceBetween), (0.0, 80.0));
+    assert_eq!(positions(Justify::SpaceAround), (15.0, 65.0));
+    assert_eq!(positions(Justify::SpaceEvenly), (20.0, 60.0));
+  }
+
+  #[test]
+  fn wrap_into_lines() {
+    let mut toolbar = Node::new(1, Layout::HorizontalWrap, DynLen::Relative(1.0));
+    toolbar.set_gap(10.0);
+    toolbar.set_line_gap(5.0);
+    for &(id, height) in &[(2, 20.0), (3, 30.0), (4, 20.0)] {
+      let mut tag = Node::new(id, Layout::Vertical, DynLen::Absolute(40.0));
+      tag.set_cross_size(Some(DynLen::Absolute(height)));
+      toolbar.add_child(tag);
+    }
+    toolbar.add_child(Node::new(5, Layout::Vertical, DynLen::Relative(1.0)));
+    let mut rects = toolbar.alloc_rect_buffer();
+
+    toolbar.layout(&mut rects, 0.0, 0.0, 100.0, 200.0, 0.0).unwrap();
+    let ids: Vec<u32> = rects.iter().map(|r| r.id).collect();
+    assert_eq!(ids, vec![2, 3, 4, 5, 1]);
+    assert_eq!(rects[0].pos, [0.0, 0.0]);
+    assert_eq!(rects[1].pos, [50.0, 0.0]);
+    assert_eq!(rects[2].pos, [0.0, 35.0]);
+    assert_eq!(rects[3].pos, [50.0, 35.0]);
+    assert_eq!(rects[3].size, [50.0, 20.0]);
+    assert_eq!(rects[4].content_size, [100.0, 200.0]);
+  }
+
+  #[test]
+  fn wrap_oversized_child() {
+    let mut tags = Node::new(1, Layout::HorizontalWrap, DynLen::Relative(1.0));
+    for &(id, w) in &[(2, 40.0), (3, 150.0), (4, 40.0)] {
+      let mut tag = Node::new(id, Layout::Vertical, DynLen::Absolute(w));
+      tag.set_cross_size(Some(DynLen::Absolute(20.0)));
+      tags.add_child(tag);
+    }
+    let mut rects = tags.alloc_rect_buffer();
+    tags.layout(&mut rects, 0.0, 0.0, 100.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[1].pos, [0.0, 20.0]);
+    assert_eq!(rects[1].size, [150.0, 20.0]);
+    assert_eq!(rects[2].pos, [0.0, 40.0]);
+    assert_eq!(rects[3].content_size, [150.0, 100.0]);
+  }
+
+  #[test]
+  fn grid_tracks_and_spans() {
+    let mut inspector = Node::new(1, Layout::Grid {
+      columns: vec![DynLen::Absolute(100.0), DynLen::Relative(1.0), DynLen::Relative(2.0)],
+      rows: vec![DynLen::Absolute(20.0), DynLen::Relative(1.0)],
+    }, DynLen::Relative(1.0));
+    inspector.set_gap(10.0);
+    let mut wide = Node::new(3, Layout::Vertical, DynLen::Relative(1.0));
+    wide.set_grid_cell(Some(GridCell::spanning(1, 1, 1, 2)));
+    inspector.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Relative(1.0)), wide]);
+    let mut rects = inspector.alloc_rect_buffer();
+
+    inspector.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[0].pos, [0.0, 0.0]);
+    assert_eq!(rects[0].size, [100.0, 20.0]);
+    assert_eq!(rects[1].pos, [110.0, 20.0]);
+    assert_eq!(rects[1].size, [290.0, 80.0]);
+
+    let mut outside = Node::new(4, Layout::Vertical, DynLen::Relative(1.0));
+    outside.set_grid_cell(Some(GridCell::new(2, 0)));
+    inspector.add_child(outside);
+    let mut rects = inspector.alloc_rect_buffer();
+    assert_eq!(inspector.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0),
+               Err(LayoutError::GridCellOutOfRange { id: 4, row: 2, column: 0, rows: 2, columns: 3 }));
+  }
+
+  #[test]
+  fn stack_layers() {
+    let mut card = Node::new(1, Layout::Stack, DynLen::Relative(1.0));
+    card.set_justify(Justify::End);
+    card.set_cross_align(Align::Start);
+    let mut content = Node::new(3, Layout::Vertical, DynLen::Relative(1.0));
+    content.add_child(Node::new(4, Layout::Vertical, DynLen::Relative(1.0)));
+    let mut badge = Node::new(5, Layout::Vertical, DynLen::Absolute(16.0));
+    badge.set_cross_size(Some(DynLen::Absolute(16.0)));
+    card.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Relative(1.0)), content, badge]);
+    let mut rects = card.alloc_rect_buffer();
+
+    card.layout(&mut rects, 0.0, 0.0, 200.0, 100.0, 0.0).unwrap();
+    let layers: Vec<(u32, f32)> = rects.iter().map(|r| (r.id, r.layer)).collect();
+    assert_eq!(layers, vec![(2, 1.0), (4, 3.0), (3, 2.0), (5, 4.0), (1, 0.0)]);
+    assert_eq!(rects[0].size, [200.0, 100.0]);
+    assert_eq!(rects[3].pos, [184.0, 0.0]);
+    assert_eq!(rects[3].size, [16.0, 16.0]);
+  }
+
+  #[test]
+  fn anchored_children() {
+    let mut dialog = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    dialog.set_padding(Sides::all(10.0));
+    let mut close = Node::new(2, Layout::Vertical, DynLen::Absolute(20.0));
+    close.set_cross_size(Some(DynLen::Absolute(20.0)));
+    close.set_position(Position::Anchored(Anchors {
+      top: Some(DynLen::Absolute(4.0)), right: Some(DynLen::Absolute(4.0)), ..Anchors::default()
+    }));
+    let mut body = Node::new(3, Layout::Vertical, DynLen::Relative(1.0));
+    body.add_child(Node::new(4, Layout::Vertical, DynLen::Relative(1.0)));
+    let mut footer = Node::new(5, Layout::Vertical, DynLen::Relative(1.0));
+    footer.set_position(Position::Anchored(Anchors {
+      left: Some(DynLen::Relative(0.25)), right: Some(DynLen::Relative(0.25)),
+      bottom: Some(DynLen::Absolute(0.0)), ..Anchors::default()
+    }));
+    dialog.add_children(vec![close, body, footer]);
+    let mut rects = dialog.alloc_rect_buffer();
+
+    dialog.layout(&mut rects, 0.0, 0.0, 200.0, 100.0, 0.0).unwrap();
+    let ids: Vec<u32> = rects.iter().map(|r| r.id).collect();
+    assert_eq!(ids, vec![2, 4, 3, 5, 1]);
+    assert_eq!(rects[0].pos, [176.0, 4.0]);
+    assert_eq!(rects[0].size, [20.0, 20.0]);
+    assert_eq!(rects[0].layer, 3.0);
+    assert_eq!(rects[2].pos, [10.0, 10.0]);
+    assert_eq!(rects[2].size, [180.0, 80.0]);
+    assert_eq!(rects[3].pos, [50.0, 0.0]);
+    assert_eq!(rects[3].size, [100.0, 100.0]);
+  }
+
+  #[test]
+  fn aspect_ratio_fit() {
+    let mut row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    let mut contain = Node::new(2, Layout::Vertical, DynLen::Relative(1.0));
+    contain.set_aspect_ratio(Some(AspectRatio::new(2.0, Fit::Contain)));
+    let mut cover = Node::new(3, Layout::Vertical, DynLen::Relative(1.0));
+    let mut aspect = AspectRatio::new(2.0, Fit::Cover);
+    aspect.align_y = Align::End;
+    cover.set_aspect_ratio(Some(aspect));
+    row.add_children(vec![contain, cover]);
+    let mut rects = row.alloc_rect_buffer();
+
+    row.layout(&mut rects, 0.0, 0.0, 400.0, 50.0, 0.0).unwrap();
+    assert_eq!(rects[0].pos, [50.0, 0.0]);
+    assert_eq!(rects[0].size, [100.0, 50.0]);
+    assert_eq!(rects[1].pos, [200.0, -50.0]);
+    assert_eq!(rects[1].size, [200.0, 100.0]);
+  }
+
+  #[test]
+  fn mixed_length_units() {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    root.set_padding(Sides::new(20.0, 0.0, 20.0, 0.0));
+    let mut panel = Node::new(3, Layout::Vertical, DynLen::Percent(25.0));
+    panel.set_cross_size(Some(DynLen::Percent(50.0)));
+    root.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(100.0)),
+                           panel,
+                           Node::new(4, Layout::Vertical, DynLen::Relative(1.0)),
+                           Node::new(5, Layout::Vertical, DynLen::Relative(3.0))]);
+    let mut rects = root.alloc_rect_buffer();
+
+    root.layout(&mut rects, 0.0, 0.0, 440.0, 100.0, 0.0).unwrap();
+    let sizes: Vec<[f32; 2]> = rects.iter().map(|r| r.size).collect();
+    assert_eq!(sizes, vec![[100.0, 100.0], [100.0, 50.0], [50.0, 100.0], [150.0, 100.0], [440.0, 100.0]]);
+
+    // Percentages are of the parent's full length, not what's left over.
+    root.layout(&mut rects, 0.0, 0.0, 840.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects[1].size, [200.0, 50.0]);
+    assert_eq!(rects[2].size, [125.0, 100.0]);
+    assert_eq!(rects[3].pos, [445.0, 0.0]);
+
+    let mut grid = Node::new(1, Layout::Grid {
+      columns: vec![DynLen::Percent(50.0), DynLen::Absolute(50.0), DynLen::Relative(1.0)],
+      rows: vec![DynLen::Percent(10.0), DynLen::Relative(1.0)],
+    }, DynLen::Relative(1.0));
+    grid.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Relative(1.0)),
+                           Node::new(3, Layout::Vertical, DynLen::Relative(1.0)),
+                           Node::new(4, Layout::Vertical, DynLen::Relative(1.0)),
+                           Node::new(5, Layout::Vertical, DynLen::Relative(1.0))]);
+    let mut rects = grid.alloc_rect_buffer();
+    grid.layout(&mut rects, 0.0, 0.0, 400.0, 200.0, 0.0).unwrap();
+    let sizes: Vec<[f32; 2]> = rects.iter().map(|r| r.size).collect();
+    assert_eq!(sizes, vec![[200.0, 20.0], [50.0, 20.0], [150.0, 20.0], [200.0, 180.0], [400.0, 200.0]]);
+  }
+
+  #[test]
+  fn auto_sized_labels() {
+    let mut toolbar = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    toolbar.set_cross_align(Align::Center);
+    toolbar.set_gap(4.0);
+    for id in 2..4 {
+      let mut label = Node::new(id, Layout::Vertical, DynLen::Auto);
+      label.set_cross_size(Some(DynLen::Auto));
+      toolbar.add_child(label);
+    }
+    toolbar.add_child(Node::new(4, Layout::Vertical, DynLen::Relative(1.0)));
+    let mut rects = toolbar.alloc_rect_buffer();
+
+    // Pretend each character is 8px wide and 16px high.
+    let text = |id| if id == 2 { "Open" } else { "Save as" };
+    let mut measured = Vec::new();
+    toolbar.layout_with_measure(&mut rects, 0.0, 0.0, 200.0, 40.0, 0.0, &mut |id, available| {
+      measured.push((id, available));
+      [text(id).len() as f32 * 8.0, 16.0]
+    }).unwrap();
+    let inf = f32::INFINITY;
+    assert_eq!(measured, vec![
+      (2, [0.0, 0.0]), (2, [inf, inf]), (3, [0.0, 0.0]), (3, [inf, inf]),
+      (2, [200.0, 40.0]), (3, [200.0, 40.0])]);
+    assert_eq!(rects[0].pos, [0.0, 12.0]);
+    assert_eq!(rects[0].size, [32.0, 16.0]);
+    assert_eq!(rects[1].pos, [36.0, 12.0]);
+    assert_eq!(rects[1].size, [56.0, 16.0]);
+    assert_eq!(rects[2].pos, [96.0, 0.0]);
+    assert_eq!(rects[2].size, [104.0, 40.0]);
+  }
+
+  #[test]
+  fn fit_content_containers() {
+    let mut root = Node::new(0, Layout::Horizontal, DynLen::Relative(1.0));
+
+    // A sidebar as tall as its items, including a measured label.
+    let mut sidebar = Node::new(1, Layout::Vertical, DynLen::Absolute(100.0));
+    sidebar.set_cross_size(Some(DynLen::Auto));
+    sidebar.set_padding(Sides::all(5.0));
+    sidebar.set_gap(10.0);
+    for id in 2..5 {
+      sidebar.add_child(Node::new(id, Layout::Vertical, DynLen::Absolute(40.0)));
+    }
+    sidebar.add_child(Node::new(5, Layout::Vertical, DynLen::Auto));
+
+    // A panel as wide as the label inside it.
+    let mut panel = Node::new(6, Layout::Vertical, DynLen::Auto);
+    panel.set_padding(Sides::all(5.0));
+    panel.add_child(Node::new(7, Layout::Vertical, DynLen::Auto));
+    root.add_children(vec![sidebar, panel]);
+
+    let mut rects = root.alloc_rect_buffer();
+    root.layout_with_measure(&mut rects, 0.0, 0.0, 400.0, 300.0, 0.0, &mut |id, _| match id {
+      5 | 7 => [30.0, 20.0],
+      _ => [0.0, 0.0],
+    }).unwrap();
+    assert_eq!(rects[3].id, 5);
+    assert_eq!(rects[3].pos, [5.0, 155.0]);
+    assert_eq!(rects[3].size, [90.0, 20.0]);
+    assert_eq!(rects[4].id, 1);
+    assert_eq!(rects[4].size, [100.0, 180.0]);
+    assert_eq!(rects[5].id, 7);
+    assert_eq!(rects[5].pos, [105.0, 5.0]);
+    assert_eq!(rects[5].size, [30.0, 20.0]);
+    assert_eq!(rects[6].id, 6);
+    assert_eq!(rects[6].pos, [100.0, 0.0]);
+    assert_eq!(rects[6].size, [40.0, 300.0]);
+  }
+
+  #[test]
+  fn flex_grow_and_shrink() {
+    let mut row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    let mut first = Node::new(2, Layout::Vertical, DynLen::Flex { basis: 100.0, grow: 1.0, shrink: 1.0 });
+    first.set_min_size(Some(80.0));
+    row.add_children(vec![first,
+                          Node::new(3, Layout::Vertical, DynLen::Flex { basis: 300.0, grow: 3.0, shrink: 1.0 }),
+                          Node::new(4, Layout::Vertical, DynLen::Absolute(50.0))]);
+    let mut rects = row.alloc_rect_buffer();
+
+    // Growing splits the free space by grow factor.
+    row.layout(&mut rects, 0.0, 0.0, 550.0, 10.0, 0.0).unwrap();
+    assert_eq!(rects[0].size[0], 125.0);
+    assert_eq!(rects[1].size[0], 375.0);
+    assert_eq!(rects[2].pos[0], 500.0);
+
+    // Shrinking is weighted by basis, so the first child would lose 50px, but
+    // stops at its min size and the second loses the rest.
+    row.layout(&mut rects, 0.0, 0.0, 250.0, 10.0, 0.0).unwrap();
+    assert_eq!(rects[0].size[0], 80.0);
+    assert_eq!(rects[1].size[0], 120.0);
+    assert_eq!(rects[2].pos[0], 200.0);
+
+    assert_eq!(row.layout(&mut rects, 0.0, 0.0, 100.0, 10.0, 0.0).unwrap_err(),
+               LayoutError::InsufficientSpace { id: 1, required: 130.0, available: 100.0 });
+  }
+
+  #[test]
+  fn reversed_layouts() {
+    // Right to left toolbar.
+    let mut toolbar = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    toolbar.set_reverse(true);
+    toolbar.set_gap(10.0);
+    toolbar.set_outer_gaps(5.0, 0.0);
+    toolbar.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(50.0)),
+                              Node::new(3, Layout::Vertical, DynLen::Absolute(100.0))]);
+    let mut rects = toolbar.alloc_rect_buffer();
+    toolbar.layout(&mut rects, 0.0, 0.0, 400.0, 30.0, 0.0).unwrap();
+    assert_eq!(rects[0].id, 2);
+    assert_eq!(rects[0].pos, [345.0, 0.0]);
+    assert_eq!(rects[1].id, 3);
+    assert_eq!(rects[1].pos, [235.0, 0.0]);
+
+    // Chat log filling up from the bottom.
+    let mut log = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    log.set_reverse(true);
+    log.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(40.0)),
+                          Node::new(3, Layout::Vertical, DynLen::Absolute(40.0))]);
+    let mut rects = log.alloc_rect_buffer();
+    log.layout(&mut rects, 0.0, 100.0, 200.0, 300.0, 0.0).unwrap();
+    assert_eq!(rects[0].pos, [0.0, 360.0]);
+    assert_eq!(rects[1].pos, [0.0, 320.0]);
+  }
+
+  #[test]
+  fn scroll_panel() {
+    let mut panel = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    panel.set_overflow(Overflow::Scroll);
+    panel.set_scroll_offset([0.0, 30.0]);
+    let mut nested = Node::new(4, Layout::Vertical, DynLen::Absolute(40.0));
+    nested.set_overflow(Overflow::Scroll);
+    nested.add_child(Node::new(5, Layout::Vertical, DynLen::Absolute(60.0)));
+    panel.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(40.0)),
+                            Node::new(3, Layout::Vertical, DynLen::Absolute(40.0)),
+                            nested,
+                            Node::new(6, Layout::Vertical, DynLen::Absolute(40.0)),
+                            Node::new(7, Layout::Vertical, DynLen::Absolute(40.0))]);
+    let mut rects = panel.alloc_rect_buffer();
+    panel.layout(&mut rects, 0.0, 0.0, 400.0, 100.0, 0.0).unwrap();
+
+    let viewport = ([0.0, 0.0], [400.0, 100.0]);
+    let ys: Vec<f32> = rects.iter().map(|r| r.pos[1]).collect();
+    assert_eq!(ys, vec![-30.0, 10.0, 50.0, 50.0, 90.0, 130.0, 0.0]);
+    assert_eq!(rects[0].size, [400.0, 40.0]);
+    assert_eq!(rects[0].clip, Some(viewport));
+    // The nested scroller's child is clipped to both.
+    assert_eq!(rects[2].id, 5);
+    assert_eq!(rects[2].size, [400.0, 60.0]);
+    assert_eq!(rects[2].clip, Some(([0.0, 50.0], [400.0, 40.0])));
+    assert_eq!(rects[3].content_size, [400.0, 60.0]);
+    assert_eq!(rects[6].content_size, [400.0, 200.0]);
+    assert_eq!(rects[6].clip, None);
+  }
+
+  #[test]
+  fn clip_children() {
+    let mut card = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    card.set_clip_children(true);
+    card.set_overflow(Overflow::Visible);
+    let mut list = Node::new(2, Layout::Vertical, DynLen::Absolute(150.0));
+    list.set_overflow(Overflow::Clip);
+    list.add_child(Node::new(3, Layout::Vertical, DynLen::Absolute(200.0)));
+    card.add_child(list);
+    let mut rects = card.alloc_rect_buffer();
+    card.layout(&mut rects, 10.0, 10.0, 100.0, 100.0, 0.0).unwrap();
+
+    // The list overflows the card, so keeps its size but is clipped to it.
+    assert_eq!(rects[1].size, [100.0, 150.0]);
+    assert_eq!(rects[1].clip, Some(([10.0, 10.0], [100.0, 100.0])));
+    // The list's child is cut off at the list, and clipped to both.
+    assert_eq!(rects[0].size, [100.0, 150.0]);
+    assert_eq!(rects[0].clip, Some(([10.0, 10.0], [100.0, 100.0])));
+    assert_eq!(rects[2].clip, None);
+  }
+
+  #[test]
+  fn incremental_relayout() {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    for &(panel, label) in &[(2, 3), (4, 5)] {
+      let mut panel = Node::new(panel, Layout::Vertical, DynLen::Relative(1.0));
+      panel.add_child(Node::new(label, Layout::Vertical, DynLen::Auto));
+      root.add_child(panel);
+    }
+    let mut rects = root.alloc_rect_buffer();
+    let layout = |root: &Node, rects: &mut [Rect], w: f32| {
+      let mut measured = Vec::new();
+      root.layout_with_measure(rects, 0.0, 0.0, w, 100.0, 0.0, &mut |id, _| {
+        measured.push(id);
+        [20.0, 10.0]
+      }).unwrap();
+      measured
+    };
+    assert_eq!(layout(&root, &mut rects, 200.0), vec![3, 3, 5, 5, 3, 5]);
+
+    // Nothing changed, so nothing is laid out.
+    assert_eq!(layout(&root, &mut rects, 200.0), Vec::<u32>::new());
+
+    // Only the changed label's panel is laid out again.
+    assert!(root.update(5, |label| label.set_margin(Sides::all(5.0))));
+    assert_eq!(layout(&root, &mut rects, 200.0), vec![5, 5, 5]);
+    assert_eq!(rects[2].pos, [105.0, 5.0]);
+
+    // Resizing lays everything out, but intrinsic sizes are kept.
+    assert_eq!(layout(&root, &mut rects, 400.0), vec![3, 5]);
+    let mut fresh = root.alloc_rect_buffer();
+    layout(&root.clone(), &mut fresh, 400.0);
+    assert_eq!(rects, fresh);
+    assert!(!root.update(6, |_| ()));
+  }
+
+  #[test]
+  fn relayout_changed_buffer() {
+    let mut root = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    let mut child = Node::new(2, Layout::Vertical, DynLen::Relative(1.0));
+    child.add_child(Node::new(3, Layout::Vertical, DynLen::Absolute(20.0)));
+    root.add_child(child);
+    let mut rects = root.alloc_rect_buffer();
+    root.layout(&mut rects, 0.0, 0.0, 100.0, 100.0, 0.0).unwrap();
+    let fresh = rects.clone();
+
+    // Sorting into painter's order, or laying out into a new buffer, doesn't
+    // stop a clean tree from giving the same rects.
+    rects.sort_by(|a, b| a.layer.partial_cmp(&b.layer).unwrap());
+    root.layout(&mut rects, 0.0, 0.0, 100.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects, fresh);
+    let mut other = root.alloc_rect_buffer();
+    root.layout(&mut other, 0.0, 0.0, 100.0, 100.0, 0.0).unwrap();
+    assert_eq!(other, fresh);
+
+    // Laying out part of the tree on its own doesn't either.
+    let child = root.find(2).unwrap();
+    child.layout(&mut other, 50.0, 50.0, 30.0, 30.0, 0.0).unwrap();
+    root.layout(&mut rects, 0.0, 0.0, 100.0, 100.0, 0.0).unwrap();
+    assert_eq!(rects, fresh);
+  }
+
+  #[test]
+  fn deep_relayout_is_linear() {
+    // Rects written laying out a chain of nodes fully, again unchanged, and
+    // again into a scrambled buffer.
+    let writes = |depth: u32| {
+      let mut chain = Node::new(depth, Layout::Vertical, DynLen::Relative(1.0));
+      for id in (0..depth).rev() {
+        let mut parent = Node::new(id, Layout::Vertical, DynLen::Relative(1.0));
+        parent.add_child(chain);
+        chain = parent;
+      }
+      let mut rects = chain.alloc_rect_buffer();
+      let mut counts = Vec::new();
+      for scramble in &[false, false, true] {
+        if *scramble {
+          rects.reverse();
+        }
+        RECT_WRITES.with(|w| w.set(0));
+        chain.layout(&mut rects, 0.0, 0.0, 100.0, 100.0, 0.0).unwrap();
+        counts.push(RECT_WRITES.with(|w| w.get()));
+      }
+      counts
+    };
+    // Deep trees recurse deeply, so need a thread with room for it.
+    let (short, long) = thread::Builder::new().stack_size(256 << 20)
+      .spawn(move || (writes(500), writes(2000))).unwrap().join().unwrap();
+    assert_eq!(short, vec![501, 0, 500]);
+    assert_eq!(long, vec![2001, 0, 2000]);
+  }
+
+  #[test]
+  fn edit_children() {
+    let mut tabs = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    for id in 2..5 {
+      tabs.add_child(Node::new(id, Layout::Vertical, DynLen::Absolute(50.0)));
+    }
+    let mut rects = tabs.alloc_rect_buffer();
+    tabs.layout(&mut rects, 0.0, 0.0, 400.0, 30.0, 0.0).unwrap();
+
+    // Close the first tab, move the last one to the front and open a new
+    // one with a child at the end.
+    assert_eq!(tabs.remove_child(0).map(|t| t.id), Some(2));
+    assert!(tabs.remove_child(5).is_none());
+    assert!(!tabs.swap_children(0, 2));
+    assert!(tabs.swap_children(0, 1));
+    let mut new_tab = Node::new(5, Layout::Vertical, DynLen::Absolute(50.0));
+    new_tab.add_child(Node::new(6, Layout::Vertical, DynLen::Relative(1.0)));
+    tabs.insert_child(10, new_tab);
+    assert!(tabs.find(6).is_some());
+    assert!(tabs.update(6, |n| n.set_margin(Sides::all(5.0))));
+
+    assert_eq!(tabs.layout(&mut rects, 0.0, 0.0, 400.0, 30.0, 0.0),
+               Err(LayoutError::BufferTooSmall { id: 1, len: 4, required: 5 }));
+    tabs.resize_rect_buffer(&mut rects);
+    tabs.layout(&mut rects, 0.0, 0.0, 400.0, 30.0, 0.0).unwrap();
+    let ids: Vec<u32> = rects.iter().map(|r| r.id).collect();
+    assert_eq!(ids, vec![4, 3, 6, 5, 1]);
+    assert_eq!(rects[2].pos, [105.0, 5.0]);
+    assert_eq!(rects[3].pos, [100.0, 0.0]);
+
+    tabs.retain_children(|t| t.id != 4);
+    assert_eq!(tabs.take_children().len(), 2);
+    tabs.resize_rect_buffer(&mut rects);
+    assert_eq!(tabs.layout(&mut rects, 0.0, 0.0, 400.0, 30.0, 0.0), Ok(1));
+  }
+
+  #[test]
+  fn inspect_and_edit_nodes() {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    root.add_children(vec![Node::new(2, Layout::Vertical, DynLen::Absolute(200.0)),
+                           Node::new(3, Layout::Vertical, DynLen::Relative(1.0))]);
+    assert_eq!(root.id(), 1);
+    assert_eq!(root.children_layout(), &Layout::Horizontal);
+    assert_eq!(root.children().map(|c| c.size()).collect::<Vec<_>>(),
+               vec![DynLen::Absolute(200.0), DynLen::Relative(1.0)]);
+    let mut rects = root.alloc_rect_buffer();
+    root.layout(&mut rects, 0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+    assert_eq!(rects[1].pos, [200.0, 0.0]);
+
+    // Collapse the panel, and stack what's inside the content.
+    for c in root.children_mut() {
+      if c.id() == 2 {
+        c.set_size(DynLen::Absolute(0.0));
+      } else {
+        c.set_children_layout(Layout::Stack);
+        c.set_id(4);
+      }
+    }
+    root.layout(&mut rects, 0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+    assert_eq!(rects[1].id, 4);
+    assert_eq!(rects[1].pos, [0.0, 0.0]);
+    assert_eq!(rects[1].size, [400.0, 300.0]);
+    assert_eq!(root.find(4).map(|n| n.children_layout()), Some(&Layout::Stack));
+  }
+
+  #[test]
+  fn layout_errors() {
+    let lay = |node: &Node, w: f32| node.layout(&mut node.alloc_rect_buffer(), 0.0, 0.0, w, 100.0, 0.0);
+    let row = |children: Vec<Node>| {
+      let mut row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+      row.add_children(children);
+      row
+    };
+    let node = |id: u32, size: DynLen| Node::new(id, Layout::Vertical, size);
+
+    assert_eq!(lay(&row(vec![node(2, DynLen::Absolute(150.0))]), 100.0),
+               Err(LayoutError::InsufficientSpace { id: 1, required: 150.0, available: 100.0 }));
+    let root = row(vec![node(2, DynLen::Relative(1.0))]);
+    assert_eq!(root.layout(&mut [Rect::new(0)], 0.0, 0.0, 100.0, 100.0, 0.0),
+               Err(LayoutError::BufferTooSmall { id: 1, len: 1, required: 2 }));
+    assert_eq!(lay(&row(vec![node(2, DynLen::Relative(0.0)), node(3, DynLen::Relative(0.0))]), 100.0),
+               Err(LayoutError::ZeroRelativeRatio { id: 1, ratio_sum: 0.0 }));
+    let mut grid = Node::new(1, Layout::Grid { columns: vec![DynLen::Relative(1.0)], rows: vec![DynLen::Relative(1.0)] },
+                             DynLen::Relative(1.0));
+    grid.add_children(vec![node(2, DynLen::Relative(1.0)), node(3, DynLen::Relative(1.0))]);
+    assert_eq!(lay(&grid, 100.0),
+               Err(LayoutError::GridCellOutOfRange { id: 3, row: 1, column: 0, rows: 1, columns: 1 }));
+    let mut square = node(2, DynLen::Relative(1.0));
+    square.set_aspect_ratio(Some(AspectRatio::new(0.0, Fit::Contain)));
+    assert_eq!(lay(&row(vec![square]), 100.0), Err(LayoutError::InvalidAspectRatio { id: 2, ratio: 0.0 }));
+
+    // Non-finite values, from the caller, the tree or the measure function.
+    assert_eq!(lay(&row(vec![]), f32::INFINITY), Err(LayoutError::NonFinite { id: 1, value: f32::INFINITY }));
+    assert_eq!(lay(&row(vec![node(2, DynLen::Absolute(f32::INFINITY))]), 100.0),
+               Err(LayoutError::NonFinite { id: 2, value: f32::INFINITY }));
+    let label = row(vec![node(2, DynLen::Auto)]);
+    assert_eq!(label.layout_with_measure(&mut label.alloc_rect_buffer(), 0.0, 0.0, 100.0, 100.0, 0.0,
+                                         &mut |_, _| [f32::INFINITY, 0.0]),
+               Err(LayoutError::NonFinite { id: 2, value: f32::INFINITY }));
+
+    // Negative sizes, lengths, padding and gaps, but not margins.
+    assert_eq!(lay(&row(vec![]), -10.0), Err(LayoutError::Negative { id: 1, value: -10.0 }));
+    assert_eq!(lay(&row(vec![node(2, DynLen::Absolute(-50.0))]), 100.0),
+               Err(LayoutError::Negative { id: 2, value: -50.0 }));
+    let mut padded = node(2, DynLen::Relative(1.0));
+    padded.set_padding(Sides::new(0.0, -5.0, 0.0, 0.0));
+    assert_eq!(lay(&row(vec![padded]), 100.0), Err(LayoutError::Negative { id: 2, value: -5.0 }));
+    let mut spaced = row(vec![node(2, DynLen::Relative(1.0))]);
+    spaced.set_gap(-1.0);
+    assert_eq!(lay(&spaced, 100.0), Err(LayoutError::Negative { id: 1, value: -1.0 }));
+    let mut pulled = node(2, DynLen::Absolute(50.0));
+    pulled.set_margin(Sides::new(-10.0, 0.0, 0.0, 0.0));
+    assert_eq!(lay(&row(vec![pulled]), 100.0), Ok(2));
+  }
+
+  #[test]
+  fn overflow_policies() {
+    let lay = |overflow: Overflow, min: Option<f32>| {
+      let mut row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+      let mut first = Node::new(2, Layout::Vertical, DynLen::Absolute(80.0));
+      first.set_min_size(min);
+      row.add_children(vec![first, Node::new(3, Layout::Vertical, DynLen::Absolute(80.0))]);
+      row.set_overflow(overflow);
+      let mut rects = row.alloc_rect_buffer();
+      row.layout(&mut rects, 0.0, 0.0, 100.0, 50.0, 0.0).unwrap();
+      rects
+    };
+
+    // Visible children keep their size and spill out.
+    let rects = lay(Overflow::Visible, None);
+    assert_eq!(rects[1].pos, [80.0, 0.0]);
+    assert_eq!(rects[1].size, [80.0, 50.0]);
+    assert_eq!(rects[1].clip, None);
+    assert_eq!(rects[2].content_size, [160.0, 50.0]);
+
+    // Shrunk children are scaled to fit, but not below their min size.
+    let rects = lay(Overflow::Shrink, None);
+    assert_eq!((rects[0].size, rects[1].pos, rects[1].size), ([50.0, 50.0], [50.0, 0.0], [50.0, 50.0]));
+    let rects = lay(Overflow::Shrink, Some(60.0));
+    assert_eq!((rects[0].size, rects[1].pos, rects[1].size), ([60.0, 50.0], [60.0, 0.0], [50.0, 50.0]));
+
+    // Clipped children are cut off at the edge, and clipped to the node.
+    let rects = lay(Overflow::Clip, None);
+    assert_eq!(rects[1].pos, [80.0, 0.0]);
+    assert_eq!(rects[1].size, [20.0, 50.0]);
+    assert_eq!(rects[1].clip, Some(([0.0, 0.0], [100.0, 50.0])));
+    assert_eq!(rects[2].content_size, [160.0, 50.0]);
+    assert_eq!(rects[2].clip, None);
+  }
+
+  #[test]
+  fn justify_nothing() {
+    for &justify in &[Justify::End, Justify::Center, Justify::SpaceBetween, Justify::SpaceAround, Justify::SpaceEvenly] {
+      let mut tags = Node::new(1, Layout::HorizontalWrap, DynLen::Relative(1.0));
+      tags.set_justify(justify);
+      tags.set_align_lines(justify);
+      let mut rects = tags.alloc_rect_buffer();
+      tags.layout(&mut rects, 0.0, 0.0, 100.0, 50.0, 0.0).unwrap();
+      assert_eq!(rects[0].content_size, [100.0, 50.0]);
+    }
+  }
+
+  #[test]
+  fn aspect_ratio_sizes() {
+    // A 16:9 thumbnail takes its height from the width of a vertical list,
+    // leaving the rest to the next item.
+    let mut list = Node::new(1, Layout::Vertical, DynLen::Relative(1.0));
+    let mut thumb = Node::new(2, Layout::Vertical, DynLen::Auto);
+    thumb.set_aspect_ratio(Some(AspectRatio::new(16.0 / 9.0, Fit::Contain)));
+    list.add_children(vec![thumb, Node::new(3, Layout::Vertical, DynLen::Relative(1.0))]);
+    let mut rects = list.alloc_rect_buffer();
+    list.layout(&mut rects, 0.0, 0.0, 320.0, 400.0, 0.0).unwrap();
+    assert_eq!(rects[0].size, [320.0, 180.0]);
+    assert_eq!(rects[1].pos, [0.0, 180.0]);
+    assert_eq!(rects[1].size, [320.0, 220.0]);
+
+    // In a row, an auto cross size follows the size, whether absolute or
+    // relative.
+    let mut row = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    for &(id, size) in &[(2, DynLen::Absolute(160.0)), (3, DynLen::Relative(1.0))] {
+      let mut thumb = Node::new(id, Layout::Vertical, size);
+      thumb.set_cross_size(Some(DynLen::Auto));
+      thumb.set_aspect_ratio(Some(AspectRatio::new(2.0, Fit::Contain)));
+      row.add_child(thumb);
+    }
+    let mut rects = row.alloc_rect_buffer();
+    row.layout(&mut rects, 0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+    assert_eq!(rects[0].size, [160.0, 80.0]);
+    assert_eq!(rects[1].pos, [160.0, 0.0]);
+    assert_eq!(rects[1].size, [240.0, 120.0]);
+
+    // Fit content parents make room for it.
+    let mut strip = Node::new(1, Layout::Horizontal, DynLen::Auto);
+    strip.set_cross_size(Some(DynLen::Auto));
+    strip.add_child(row.remove_child(0).unwrap());
+    let mut root = Node::new(0, Layout::Vertical, DynLen::Relative(1.0));
+    root.add_child(strip);
+    let mut rects = root.alloc_rect_buffer();
+    root.layout(&mut rects, 0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+    assert_eq!(rects[1].size, [160.0, 80.0]);
+  }
+
+  #[test]
+  fn nodes_are_send() {
+    fn send<T: Send>() {}
+    send::<Node>();
+    send::<LayoutTree>();
+  }
+}
@@ -3,7 +3,7 @@ extern crate glium;
 
 use glium::backend::glutin_backend::GlutinFacade;
 
-mod lib;
+extern crate guilay as lib;
 
 #[derive(Copy, Clone)]
 struct Vertex {
@@ -109,7 +109,9 @@ fn main() {
   let (display_w, display_h) = display.get_window().unwrap().get_inner_size().unwrap();
 
   // Layout node tree
-  node_tree.layout(&mut rects[..], 0.0, 0.0, display_w as f32, display_h as f32, 0.0);
+  if let Err(e) = node_tree.layout(&mut rects[..], 0.0, 0.0, display_w as f32, display_h as f32, 0.0) {
+    println!("Layout failed: {}", e);
+  }
 
   // Buffer node tree rects as vbo
   let mut vbo_data = Vec::with_capacity(rects.len() * 6);
@@ -132,7 +134,9 @@ fn main() {
         glium::glutin::Event::Closed => { return }   // the window has been closed by the user
         glium::glutin::Event::Resized(w, h) => {
           // Layout node tree
-          node_tree.layout(&mut rects[..], 0.0, 0.0, w as f32, h as f32, 0.0);
+          if let Err(e) = node_tree.layout(&mut rects[..], 0.0, 0.0, w as f32, h as f32, 0.0) {
+            println!("Layout failed: {}", e);
+          }
 
           // Buffer node tree rects as vbo
           let mut vbo_data = Vec::with_capacity(rects.len() * 6);
@@ -0,0 +1,76 @@
+//! Rect buffers indexed by node id, so the rect of a given node can be found
+//! without scanning the buffer.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use {LayoutError, Rect};
+
+/// A laid out rect buffer, with the index of each node's rect by id.
+#[derive(Debug, Clone)]
+pub struct LayoutResult {
+  rects: Vec<Rect>,
+  indices: HashMap<u32, usize>,
+}
+
+impl LayoutResult {
+  /// Indexes a rect buffer that has been laid out.
+  /// # Errors
+  /// LayoutError::DuplicateId if more than one rect has the same id.
+  pub fn new(rects: Vec<Rect>) -> Result<LayoutResult, LayoutError> {
+    let mut indices = HashMap::with_capacity(rects.len());
+    for (i, r) in rects.iter().enumerate() {
+      if indices.insert(r.id, i).is_some() {
+        return Err(LayoutError::DuplicateId { id: r.id });
+      }
+    }
+    Ok(LayoutResult { rects, indices })
+  }
+
+  /// The rect of the node with the given id.
+  pub fn get(&self, id: u32) -> Option<&Rect> {
+    self.index_of(id).map(|i| &self.rects[i])
+  }
+
+  /// The index in the buffer of the rect of the node with the given id.
+  pub fn index_of(&self, id: u32) -> Option<usize> {
+    self.indices.get(&id).cloned()
+  }
+
+  /// Gives back the rect buffer, e.g. to lay out into again.
+  pub fn into_rects(self) -> Vec<Rect> {
+    self.rects
+  }
+}
+
+/// Derefs to the rects, in the buffer's order.
+impl Deref for LayoutResult {
+  type Target = [Rect];
+
+  fn deref(&self) -> &[Rect] {
+    &self.rects
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {DynLen, Layout, Node};
+
+  #[test]
+  fn lookup_by_id() {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    root.add_children(vec![Node::new(7, Layout::Vertical, DynLen::Absolute(100.0)),
+                           Node::new(3, Layout::Vertical, DynLen::Relative(1.0))]);
+    let result = root.layout_result(0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+    assert_eq!(result.len(), 3);
+    assert_eq!(result.index_of(3), Some(1));
+    assert_eq!(result.get(3).map(|r| r.pos), Some([100.0, 0.0]));
+    assert_eq!(result.get(1).map(|r| r.size), Some([400.0, 300.0]));
+    assert!(result.get(2).is_none());
+
+    root.update(3, |n| n.set_id(7));
+    assert_eq!(root.layout_result(0.0, 0.0, 400.0, 300.0, 0.0).unwrap_err(),
+               LayoutError::DuplicateId { id: 7 });
+  }
+}
@@ -0,0 +1,306 @@
+//! An arena of nodes addressed by handles, for trees that change after
+//! they're built. Nodes can be reached directly by handle, know their parent,
+//! and can be moved to a new parent along with their subtree.
+
+use std::mem;
+
+use {LayoutCtx, LayoutError, Node, NodeProps, Rect, Tree};
+
+/// A handle to a node in a LayoutTree. Handles stay valid until their node
+/// is removed, and aren't reused by nodes added later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeHandle {
+  index: usize,
+  generation: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+  node: Option<Node>,
+  generation: u32,
+  parent: Option<NodeHandle>,
+}
+
+/// A node tree kept in an arena. Nodes in the tree keep their children as
+/// handles, so are reached as their NodeProps, and changed through update()
+/// rather than directly, which also marks their ancestors as needing layout.
+/// Their children are changed with the tree's own methods. Like Node, a LayoutTree is Send
+/// but not Sync.
+#[derive(Debug, Clone, Default)]
+pub struct LayoutTree {
+  slots: Vec<Slot>,
+  free: Vec<usize>,
+}
+
+impl LayoutTree {
+  pub fn new() -> LayoutTree {
+    LayoutTree { slots: Vec::new(), free: Vec::new() }
+  }
+
+  /// Adds a node to the tree without a parent, e.g. as the root to lay out.
+  /// Its children, and theirs, are moved into the tree with it.
+  pub fn insert(&mut self, node: Node) -> NodeHandle {
+    self.insert_with_parent(node, None)
+  }
+
+  fn insert_with_parent(&mut self, mut node: Node, parent: Option<NodeHandle>) -> NodeHandle {
+    let children = mem::take(&mut node.children);
+    node.child_handles.clear();
+    node.dirty.set(true);
+    let handle = match self.free.pop() {
+      Some(index) => {
+        let slot = &mut self.slots[index];
+        slot.node = Some(node);
+        slot.parent = parent;
+        NodeHandle { index, generation: slot.generation }
+      }
+      None => {
+        self.slots.push(Slot { node: Some(node), generation: 0, parent });
+        NodeHandle { index: self.slots.len() - 1, generation: 0 }
+      }
+    };
+    self.adopt(handle, children);
+    handle
+  }
+
+  /// Moves the given nodes into the tree as the last children of `parent`.
+  fn adopt(&mut self, parent: NodeHandle, children: Vec<Node>) {
+    for c in children {
+      let c = self.insert_with_parent(c, Some(parent));
+      self.node_mut(parent).child_handles.push(c);
+    }
+  }
+
+  fn slot(&self, handle: NodeHandle) -> Option<&Slot> {
+    self.slots.get(handle.index).filter(|s| s.generation == handle.generation && s.node.is_some())
+  }
+
+  /// Whether the node with the given handle is still in the tree.
+  pub fn contains(&self, handle: NodeHandle) -> bool {
+    self.slot(handle).is_some()
+  }
+
+  /// The properties of the node with the given handle.
+  pub fn get(&self, handle: NodeHandle) -> Option<&NodeProps> {
+    self.node(handle).map(|n| &**n)
+  }
+
+  /// The node with the given handle. Its children are in `child_handles`,
+  /// so it shouldn't be handed out.
+  pub(crate) fn node(&self, handle: NodeHandle) -> Option<&Node> {
+    self.slot(handle).and_then(|s| s.node.as_ref())
+  }
+
+  /// Like get(), for handles known to be in the tree.
+  fn node_mut(&mut self, handle: NodeHandle) -> &mut Node {
+    self.slots[handle.index].node.as_mut().expect("node in tree")
+  }
+
+  /// The parent of a node, or None for nodes without one (or not in the
+  /// tree).
+  pub fn parent(&self, handle: NodeHandle) -> Option<NodeHandle> {
+    self.slot(handle).and_then(|s| s.parent)
+  }
+
+  /// The children of a node, in order.
+  pub fn children(&self, handle: NodeHandle) -> &[NodeHandle] {
+    self.node(handle).map_or(&[], |n| &n.child_handles)
+  }
+
+  /// Changes the properties of the node with the given handle with `f`,
+  /// marking it and its ancestors as needing layout.
+  /// # Returns
+  /// Whether the node was in the tree.
+  pub fn update<F: FnOnce(&mut NodeProps)>(&mut self, handle: NodeHandle, f: F) -> bool {
+    if !self.contains(handle) {
+      return false;
+    }
+    f(self.node_mut(handle));
+    self.mark_dirty(handle);
+    true
+  }
+
+  /// Marks a node and its ancestors as needing layout.
+  fn mark_dirty(&self, handle: NodeHandle) {
+    let mut curr = Some(handle);
+    while let Some(slot) = curr.and_then(|h| self.slot(h)) {
+      slot.node.as_ref().unwrap().dirty.set(true);
+      curr = slot.parent;
+    }
+  }
+
+  /// Moves a node, along with its subtree, to be the child of `parent` at the
+  /// given index (or the last child, if past the end), taking it from its
+  /// old parent.
+  /// # Returns
+  /// False, changing nothing, if either node isn't in the tree, or `child`
+  /// is `parent` or one of its ancestors.
+  pub fn insert_child(&mut self, parent: NodeHandle, index: usize, child: NodeHandle) -> bool {
+    if !self.contains(parent) || !self.contains(child) {
+      return false;
+    }
+    let mut ancestor = Some(parent);
+    while let Some(a) = ancestor {
+      if a == child {
+        return false;
+      }
+      ancestor = self.parent(a);
+    }
+    self.detach(child);
+    let children = &mut self.node_mut(parent).child_handles;
+    let index = index.min(children.len());
+    children.insert(index, child);
+    self.slots[child.index].parent = Some(parent);
+    self.mark_dirty(parent);
+    true
+  }
+
+  /// Moves a node, along with its subtree, to be the last child of `parent`.
+  /// See insert_child().
+  pub fn append_child(&mut self, parent: NodeHandle, child: NodeHandle) -> bool {
+    let index = self.children(parent).len();
+    self.insert_child(parent, index, child)
+  }
+
+  /// Takes a node, along with its subtree, from its parent, leaving it in the
+  /// tree without one.
+  /// # Returns
+  /// Whether the node was in the tree.
+  pub fn detach(&mut self, handle: NodeHandle) -> bool {
+    if !self.contains(handle) {
+      return false;
+    }
+    if let Some(parent) = self.slots[handle.index].parent.take() {
+      self.node_mut(parent).child_handles.retain(|&c| c != handle);
+      self.mark_dirty(parent);
+    }
+    true
+  }
+
+  /// Removes a node and its subtree from the tree, invalidating their
+  /// handles.
+  /// # Returns
+  /// The node, with its subtree as its children, or None if it wasn't in the
+  /// tree.
+  pub fn remove(&mut self, handle: NodeHandle) -> Option<Node> {
+    if !self.detach(handle) {
+      return None;
+    }
+    Some(self.take(handle))
+  }
+
+  fn take(&mut self, handle: NodeHandle) -> Node {
+    let slot = &mut self.slots[handle.index];
+    let mut node = slot.node.take().expect("node in tree");
+    slot.generation = slot.generation.wrapping_add(1);
+    slot.parent = None;
+    self.free.push(handle.index);
+    let handles = mem::take(&mut node.child_handles);
+    node.children = handles.into_iter().map(|c| self.take(c)).collect();
+    node.dirty.set(true);
+    node
+  }
+
+  /// Creates a buffer of Rect structs to be used when laying out the tree
+  /// under `root`, or an error if `root` isn't in the tree.
+  pub fn alloc_rect_buffer(&self, root: NodeHandle) -> Result<Vec<Rect>, LayoutError> {
+    let mut buf = Vec::new();
+    self.root(root)?.alloc_rects(Tree::Arena(self), &mut buf);
+    Ok(buf)
+  }
+
+  /// Resizes a buffer from alloc_rect_buffer() to fit the tree under `root`
+  /// after nodes have been added or removed. Leaves the buffer alone if
+  /// `root` isn't in the tree.
+  pub fn resize_rect_buffer(&self, root: NodeHandle, buf: &mut Vec<Rect>) -> Result<(), LayoutError> {
+    let count = self.root(root)?.rect_count(Tree::Arena(self));
+    buf.resize(count, Rect::new(0));
+    Ok(())
+  }
+
+  /// Layout the tree under `root`, like Node::layout(), into a buffer in the
+  /// same order. Fails with NotInTree if `root` has been removed.
+  #[allow(clippy::too_many_arguments)]
+  pub fn layout(&self, root: NodeHandle, rect_buffer: &mut [Rect], x: f32, y: f32, w: f32, h: f32, layer: f32) -> Result<usize, LayoutError> {
+    self.layout_with_measure(root, rect_buffer, x, y, w, h, layer, &mut |_, _| [0.0, 0.0])
+  }
+
+  /// Like layout(), measuring auto sized nodes like Node::layout_with_measure().
+  #[allow(clippy::too_many_arguments)]
+  pub fn layout_with_measure(&self, root: NodeHandle, rect_buffer: &mut [Rect], x: f32, y: f32, w: f32, h: f32, layer: f32, measure: &mut dyn FnMut(u32, [f32; 2]) -> [f32; 2]) -> Result<usize, LayoutError> {
+    self.root(root)?.layout_root(rect_buffer, [x, y], [w, h], layer, &mut LayoutCtx::new(Tree::Arena(self), measure))
+  }
+
+  fn root(&self, handle: NodeHandle) -> Result<&Node, LayoutError> {
+    self.node(handle).ok_or(LayoutError::NotInTree { handle })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {DynLen, Layout, Sides};
+
+  fn sidebar_tree() -> Node {
+    let mut root = Node::new(1, Layout::Horizontal, DynLen::Relative(1.0));
+    let mut sidebar = Node::new(2, Layout::Vertical, DynLen::Absolute(100.0));
+    for id in 3..5 {
+      sidebar.add_child(Node::new(id, Layout::Vertical, DynLen::Absolute(40.0)));
+    }
+    root.add_children(vec![sidebar, Node::new(5, Layout::Vertical, DynLen::Relative(1.0))]);
+    root
+  }
+
+  #[test]
+  fn same_layout_as_nodes() {
+    let root = sidebar_tree();
+    let mut expected = root.alloc_rect_buffer();
+    root.layout(&mut expected, 0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+
+    let mut tree = LayoutTree::new();
+    let handle = tree.insert(root);
+    let mut rects = tree.alloc_rect_buffer(handle).unwrap();
+    assert_eq!(tree.layout(handle, &mut rects, 0.0, 0.0, 400.0, 300.0, 0.0), Ok(5));
+    assert_eq!(rects, expected);
+  }
+
+  #[test]
+  fn reparent_and_remove() {
+    let mut tree = LayoutTree::new();
+    let root = tree.insert(sidebar_tree());
+    let sidebar = tree.children(root)[0];
+    let content = tree.children(root)[1];
+    let item = tree.children(sidebar)[1];
+    assert_eq!(tree.parent(item), Some(sidebar));
+    assert_eq!(tree.get(item).map(|n| n.id()), Some(4));
+    assert!(!tree.append_child(item, sidebar));
+
+    // Move the second item into the content, and pad it.
+    assert!(tree.append_child(content, item));
+    assert!(tree.update(content, |n| n.set_padding(Sides::all(10.0))));
+    assert_eq!(tree.parent(item), Some(content));
+    assert_eq!(tree.children(sidebar).len(), 1);
+    let mut rects = tree.alloc_rect_buffer(root).unwrap();
+    tree.layout(root, &mut rects, 0.0, 0.0, 400.0, 300.0, 0.0).unwrap();
+    assert_eq!(rects[2].id, 4);
+    assert_eq!(rects[2].pos, [110.0, 10.0]);
+    assert_eq!(rects[2].size, [280.0, 40.0]);
+
+    // Removing the content gives back its subtree, and invalidates handles.
+    let removed = tree.remove(content).unwrap();
+    assert_eq!(removed.alloc_rect_buffer().len(), 2);
+    assert!(!tree.contains(item));
+    assert_eq!(tree.children(root), &[sidebar]);
+    let replacement = tree.insert(Node::new(6, Layout::Vertical, DynLen::Relative(1.0)));
+    assert!(tree.get(content).is_none());
+    assert!(tree.append_child(root, replacement));
+    assert_eq!(tree.alloc_rect_buffer(root).unwrap().len(), 4);
+
+    // Stale handles are reported rather than laid out.
+    let stale = LayoutError::NotInTree { handle: content };
+    assert_eq!(tree.alloc_rect_buffer(content), Err(stale.clone()));
+    assert_eq!(tree.resize_rect_buffer(content, &mut rects), Err(stale.clone()));
+    assert_eq!(rects.len(), 5);
+    assert_eq!(tree.layout(content, &mut rects, 0.0, 0.0, 400.0, 300.0, 0.0), Err(stale));
+  }
+}